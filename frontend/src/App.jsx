@@ -28,9 +28,10 @@ export default function App() {
       .catch((e) => setError(`Failed to load identities: ${e.message}`));
   }, []);
 
-  // SSE connection for live packet stream
+  // SSE connection for the packet stream — replays the server's history first;
+  // after a reconnect the browser resumes from the last event ID
   useEffect(() => {
-    const es = new EventSource(`${API_BASE}/packets/stream`);
+    const es = new EventSource(`${API_BASE}/packets/stream?history=true`);
     eventSourceRef.current = es;
 
     es.addEventListener('packet', (e) => {
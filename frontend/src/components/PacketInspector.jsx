@@ -14,8 +14,16 @@ const STEP_COLORS = {
   mediator_ack:      { bg: 'bg-green-900/30', border: 'border-green-700', badge: 'bg-green-700 text-green-100' },
   trust_ping:        { bg: 'bg-purple-900/30', border: 'border-purple-700', badge: 'bg-purple-700 text-purple-100' },
   trust_pong:        { bg: 'bg-purple-900/30', border: 'border-purple-600', badge: 'bg-purple-600 text-purple-100' },
+  features_query:    { bg: 'bg-cyan-900/30', border: 'border-cyan-700', badge: 'bg-cyan-700 text-cyan-100' },
+  features_disclose: { bg: 'bg-cyan-900/30', border: 'border-cyan-600', badge: 'bg-cyan-600 text-cyan-100' },
+  problem_report:    { bg: 'bg-pink-900/30', border: 'border-pink-700', badge: 'bg-pink-700 text-pink-100' },
   message_pickup:    { bg: 'bg-green-900/30', border: 'border-green-800', badge: 'bg-green-800 text-green-100' },
   message_delivery:  { bg: 'bg-green-900/30', border: 'border-green-600', badge: 'bg-green-600 text-green-100' },
+  oob_invitation:    { bg: 'bg-indigo-900/30', border: 'border-indigo-700', badge: 'bg-indigo-700 text-indigo-100' },
+  did_rotation:      { bg: 'bg-amber-900/30', border: 'border-amber-700', badge: 'bg-amber-700 text-amber-100' },
+  message_expired:   { bg: 'bg-rose-900/30', border: 'border-rose-700', badge: 'bg-rose-700 text-rose-100' },
+  file_transfer:     { bg: 'bg-lime-900/30', border: 'border-lime-700', badge: 'bg-lime-700 text-lime-100' },
+  message_delete:    { bg: 'bg-gray-800/50', border: 'border-gray-600', badge: 'bg-gray-600 text-gray-100' },
 };
 
 function didAlias(did) {
@@ -78,6 +86,29 @@ function PacketCard({ packet }) {
               {copied ? '✓ Copied' : '📋 Copy'}
             </button>
           </div>
+          {packet.annotations && (
+            <div className="px-4 pt-3 pr-24 text-[11px] text-gray-400 space-y-0.5">
+              {Object.entries(packet.annotations).map(([key, value]) =>
+                key === 'envelope' && value ? (
+                  <details key={key}>
+                    <summary className="cursor-pointer text-gray-500">
+                      envelope: <span className="font-mono">{value.format} decoded</span>
+                    </summary>
+                    <pre className="mt-1 p-2 bg-gray-900 rounded font-mono overflow-x-auto">
+                      {JSON.stringify(value, null, 2)}
+                    </pre>
+                  </details>
+                ) : (
+                  <div key={key}>
+                    <span className="text-gray-500">{key}:</span>{' '}
+                    <span className="font-mono">
+                      {typeof value === 'string' ? value : JSON.stringify(value)}
+                    </span>
+                  </div>
+                )
+              )}
+            </div>
+          )}
           <pre className="p-4 text-xs text-gray-300 overflow-x-auto max-h-80 overflow-y-auto font-mono leading-relaxed">
             {jsonStr}
           </pre>
@@ -115,6 +146,7 @@ export default function PacketInspector({ packets }) {
           className="text-xs bg-gray-800 text-gray-300 rounded px-2 py-1 border border-gray-700 focus:outline-none"
         >
           <option value="all">All Steps</option>
+          <option value="oob_invitation">⓪ OOB Invitation</option>
           <option value="plaintext_message">① Plaintext</option>
           <option value="signed_envelope">② Signed</option>
           <option value="encrypted_payload">③ Encrypted</option>
@@ -123,8 +155,15 @@ export default function PacketInspector({ packets }) {
           <option value="mediator_ack">⑤ ACK</option>
           <option value="trust_ping">Ping</option>
           <option value="trust_pong">Pong</option>
+          <option value="features_query">Features Query</option>
+          <option value="features_disclose">Features Disclose</option>
+          <option value="problem_report">⚠ Problem Report</option>
           <option value="message_pickup">⑥ Pickup</option>
           <option value="message_delivery">⑥ Delivery</option>
+          <option value="message_expired">⌛ Expired</option>
+          <option value="message_delete">✕ Delete</option>
+          <option value="did_rotation">↻ DID Rotation</option>
+          <option value="file_transfer">⇪ File Transfer</option>
         </select>
       </div>
 
@@ -1,6 +1,8 @@
 /// REST + SSE endpoints served by Axum.
 ///
-/// All handlers receive `Arc<AppState>` via Axum's state extraction.
+/// All handlers receive `Arc<AppState>` via Axum's state extraction, except in
+/// replay mode where the read-only endpoints receive `Arc<ReplayState>`.
+use std::collections::{BTreeMap, VecDeque};
 use std::convert::Infallible;
 use std::sync::Arc;
 use std::time::Duration;
@@ -17,13 +19,22 @@ use axum::{
 use futures::stream::Stream;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use tokio_stream::wrappers::BroadcastStream;
-use tokio_stream::StreamExt;
+use tokio::sync::broadcast::{self, error::RecvError};
 use tracing::error;
 
-use crate::identity::IdentityInfo;
+use crate::archive::SessionArchive;
+use crate::did_peer;
+use crate::error::FlowError;
+use crate::identity::{IdentityInfo, IdentityRegistry};
+use crate::inbound;
 use crate::mediator::AppState;
+use crate::packet_logger::{LoggedPacket, PacketEvent, PacketFilter, PacketLog, Replay};
+use crate::replay::ReplayState;
 use crate::flows;
+use crate::flows::attachments::AttachmentSpec;
+use crate::flows::delete_messages::DeleteTarget;
+use crate::flows::discover_features::{FeatureKind, FeatureQuery};
+use crate::flows::send_message::{Expiry, SendMode, SendOptions};
 
 // ─── Request / Response types ───────────────────────────────────────────────
 
@@ -31,7 +42,29 @@ use crate::flows;
 pub struct SendMessageRequest {
     pub from: String,
     pub to: String,
+    /// Message text (may be empty when attachments are supplied).
+    #[serde(default)]
     pub body: String,
+    /// Envelope protection (`authcrypt` when omitted).
+    #[serde(default)]
+    pub mode: SendMode,
+    /// Send without any sender identity (implies `anoncrypt` unless `plaintext`).
+    #[serde(default)]
+    pub anonymous: bool,
+    /// ID of the message being replied to; the reply joins its thread.
+    pub reply_to: Option<String>,
+    /// Thread to post in (defaults to the thread of `reply_to`).
+    pub thid: Option<String>,
+    /// Parent thread, to start a sub-thread.
+    pub pthid: Option<String>,
+    /// Lifetime in seconds (default 300); negative sends an already-expired message.
+    pub expires_in: Option<i64>,
+    /// Send without any `expires_time`.
+    #[serde(default)]
+    pub no_expiry: bool,
+    /// DIDComm attachments: `data` holds `base64`, `json` or `links` + `hash`.
+    #[serde(default)]
+    pub attachments: Vec<AttachmentSpec>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -40,25 +73,179 @@ pub struct PingRequest {
     pub to: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct DiscoverFeaturesRequest {
+    pub from: String,
+    /// Peer alias or "mediator".
+    pub to: String,
+    /// Defaults to every protocol (`[{"feature_type": "protocol", "match": "*"}]`).
+    pub queries: Option<Vec<FeatureQuery>>,
+}
+
+/// Page size used by `GET /api/messages/{alias}` when `limit` is omitted.
+const DEFAULT_FETCH_LIMIT: usize = 50;
+
+/// The mediator's upper bound on a single fetch.
+const MAX_FETCH_LIMIT: usize = 100;
+
+/// What the mediator does with fetched messages.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeletePolicy {
+    /// Leave them queued; page through with `start_id`.
+    #[default]
+    DoNotDelete,
+    /// Delete them as they are handed out.
+    DeleteOnRetrieve,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FetchQuery {
+    /// Page size (1-100, defaults to 50).
+    pub limit: Option<usize>,
+    /// Cursor — the `next_cursor` of the previous page.
+    pub start_id: Option<String>,
+    #[serde(default)]
+    pub delete_policy: DeletePolicy,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteMessagesRequest {
+    /// Message IDs to delete, or `"all"` for the whole inbox.
+    pub message_ids: DeleteTarget,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PickupRequest {
+    /// Delivery-request limit (defaults to 10).
+    pub limit: Option<usize>,
+    /// Send `messages-received` for what was delivered (defaults to true).
+    pub acknowledge: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateInvitationRequest {
+    /// Inviting identity.
+    pub from: String,
+    pub goal_code: Option<String>,
+    pub goal: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcceptInvitationRequest {
+    /// Identity accepting the invitation.
+    pub alias: String,
+    /// The invitation as JSON ...
+    pub invitation: Option<serde_json::Value>,
+    /// ... or as an `_oob` URL.
+    pub url: Option<String>,
+}
+
+/// Every active identity keyed by lower-cased alias (`{"alice": {...}, "bob": {...}}`).
+pub type IdentitiesResponse = BTreeMap<String, IdentityInfo>;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateIdentityRequest {
+    pub alias: String,
+    /// Mediator to route through (defaults to the environment's mediator).
+    pub mediator_did: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RemoveIdentityQuery {
+    /// Also delete the identity's account on the mediator.
+    #[serde(default)]
+    pub delete_account: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RotateDidRequest {
+    /// Peer to send the first `from_prior` message to straight away.
+    pub notify: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListenerRequest {
+    /// Stop (true) or restart (false) the identity's inbound listener.
+    pub paused: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ImportQuery {
+    /// Add the archive's events after the current ones instead of replacing them.
+    #[serde(default)]
+    pub append: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PacketStreamQuery {
+    /// Replay the retained history before streaming live events (ignored
+    /// when resuming with `Last-Event-ID`).
+    #[serde(default)]
+    pub history: bool,
+}
+
+/// Query parameters of `POST /api/files/send`; the request body is the raw file.
+#[derive(Debug, Deserialize)]
+pub struct SendFileQuery {
+    pub from: String,
+    pub to: String,
+    pub filename: Option<String>,
+    /// Bytes per chunk message (default 64 KiB).
+    pub chunk_size: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DidQuery {
+    pub did: String,
+}
+
+/// Fully resolved DID document plus a decoded view of did:peer segments.
 #[derive(Debug, Serialize)]
-pub struct IdentitiesResponse {
-    pub alice: IdentityInfo,
-    pub bob: IdentityInfo,
+pub struct DidDocumentResponse {
+    pub did: String,
+    /// Registered alias owning this DID, if any.
+    pub alias: Option<String>,
+    pub method: String,
+    pub cache_hit: bool,
+    pub document: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer: Option<did_peer::PeerDidDecoded>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ApiError {
     pub error: String,
+    /// Machine-readable error code (`unknown_alias`, `timeout`, `acl_denied`, ...).
+    pub code: &'static str,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub step: Option<String>,
 }
 
-fn api_error(status: StatusCode, msg: impl Into<String>, step: Option<&str>) -> Response {
+/// Server state that owns a packet log: the live `AppState` or a replay.
+pub trait PacketSource: Send + Sync + 'static {
+    fn packet_log(&self) -> &PacketLog;
+}
+
+impl PacketSource for AppState {
+    fn packet_log(&self) -> &PacketLog {
+        &self.packet_tx
+    }
+}
+
+impl PacketSource for ReplayState {
+    fn packet_log(&self) -> &PacketLog {
+        &self.packet_tx
+    }
+}
+
+fn api_error(err: FlowError, step: Option<&str>) -> Response {
     let body = ApiError {
-        error: msg.into(),
+        error: err.to_string(),
+        code: err.code(),
         step: step.map(|s| s.to_string()),
     };
-    (status, Json(body)).into_response()
+    (err.status(), Json(body)).into_response()
 }
 
 // ─── GET /api/identities ────────────────────────────────────────────────────
@@ -66,35 +253,228 @@ fn api_error(status: StatusCode, msg: impl Into<String>, step: Option<&str>) ->
 pub async fn get_identities(
     State(state): State<Arc<AppState>>,
 ) -> impl IntoResponse {
-    Json(IdentitiesResponse {
-        alice: state.alice_info.clone(),
-        bob: state.bob_info.clone(),
-    })
+    let identities: IdentitiesResponse = state.identities.read().await.infos();
+    Json(identities)
 }
 
-// ─── POST /api/messages/send ────────────────────────────────────────────────
+// ─── POST /api/identities ───────────────────────────────────────────────────
 
-pub async fn send_message(
+pub async fn create_identity(
     State(state): State<Arc<AppState>>,
-    Json(req): Json<SendMessageRequest>,
+    Json(req): Json<CreateIdentityRequest>,
 ) -> Response {
-    if req.body.trim().is_empty() {
-        return api_error(StatusCode::BAD_REQUEST, "body cannot be empty", None);
+    let alias = req.alias.trim();
+    if let Err(e) = IdentityRegistry::validate_alias(alias) {
+        return api_error(e, None);
+    }
+    if state.identities.read().await.contains(alias) {
+        return api_error(FlowError::AliasExists(alias.to_string()), None);
     }
 
-    match flows::send_message::send_message(&state, &req.from, &req.to, &req.body).await {
-        Ok(events) => (
+    match state.create_identity(alias, req.mediator_did.as_deref()).await {
+        Ok(identity) => (StatusCode::CREATED, Json(identity.info)).into_response(),
+        Err(e) => {
+            error!("create_identity error: {e}");
+            api_error(e, Some("create_identity"))
+        }
+    }
+}
+
+// ─── POST /api/identities/{alias}/rotate ────────────────────────────────────
+
+pub async fn rotate_did(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(alias): axum::extract::Path<String>,
+    req: Option<Json<RotateDidRequest>>,
+) -> Response {
+    let req = req.map(|Json(r)| r).unwrap_or_default();
+    let previous_did = state.identity(&alias).await.map(|i| i.did().to_string());
+
+    match flows::did_rotation::rotate_did(&state, &alias, req.notify.as_deref()).await {
+        Ok((events, identity)) => (
             StatusCode::OK,
             Json(json!({
-                "status": "delivered",
+                "previous_did": previous_did,
+                "identity": identity.info,
+                "from_prior": identity.from_prior,
                 "events_count": events.len(),
                 "correlation_id": events.first().and_then(|e| e.correlation_id.clone()),
             })),
         )
             .into_response(),
+        Err(e) => {
+            error!("rotate_did error: {e}");
+            api_error(e, Some("did_rotation"))
+        }
+    }
+}
+
+// ─── GET /api/identities/{alias}/contacts ───────────────────────────────────
+
+pub async fn get_contacts(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(alias): axum::extract::Path<String>,
+) -> Response {
+    if state.identity(&alias).await.is_none() {
+        return api_error(FlowError::unknown_alias("alias", &alias), None);
+    }
+    Json(state.contacts.list(&alias)).into_response()
+}
+
+// ─── GET/PUT /api/identities/{alias}/listener ───────────────────────────────
+
+pub async fn get_listener(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(alias): axum::extract::Path<String>,
+) -> Response {
+    let Some(identity) = state.identity(&alias).await else {
+        return api_error(FlowError::unknown_alias("alias", &alias), None);
+    };
+    Json(json!({
+        "alias": identity.alias(),
+        "paused": state.inbound.is_paused(&alias),
+    }))
+    .into_response()
+}
+
+pub async fn set_listener(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(alias): axum::extract::Path<String>,
+    Json(req): Json<ListenerRequest>,
+) -> Response {
+    let Some(identity) = state.identity(&alias).await else {
+        return api_error(FlowError::unknown_alias("alias", &alias), None);
+    };
+    inbound::pause_listener(&state, &identity, req.paused);
+    Json(json!({
+        "alias": identity.alias(),
+        "paused": req.paused,
+    }))
+    .into_response()
+}
+
+// ─── DELETE /api/identities/{alias} ─────────────────────────────────────────
+
+pub async fn remove_identity(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(alias): axum::extract::Path<String>,
+    axum::extract::Query(query): axum::extract::Query<RemoveIdentityQuery>,
+) -> Response {
+    match state.remove_identity(&alias, query.delete_account).await {
+        Some(removal) => (StatusCode::OK, Json(removal)).into_response(),
+        None => api_error(FlowError::unknown_alias("alias", &alias), None),
+    }
+}
+
+// ─── GET /api/identities/{alias}/did-document ───────────────────────────────
+
+pub async fn get_did_document(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(alias): axum::extract::Path<String>,
+) -> Response {
+    let Some(identity) = state.identity(&alias).await else {
+        return api_error(FlowError::unknown_alias("alias", &alias), None);
+    };
+    did_document_response(&state, identity.did(), Some(identity.alias())).await
+}
+
+// ─── GET /api/did-document?did=… ────────────────────────────────────────────
+
+pub async fn resolve_did_document(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Query(query): axum::extract::Query<DidQuery>,
+) -> Response {
+    let alias = state
+        .identities
+        .read()
+        .await
+        .find_by_did(&query.did)
+        .map(|i| i.alias().to_string());
+    did_document_response(&state, &query.did, alias.as_deref()).await
+}
+
+async fn did_document_response(state: &AppState, did: &str, alias: Option<&str>) -> Response {
+    let resolved = match state.tdk.did_resolver().resolve(did).await {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            error!("DID resolution failed for {did}: {e}");
+            return api_error(FlowError::Resolution(format!("{e}")), Some("resolve_did"));
+        }
+    };
+
+    let body = DidDocumentResponse {
+        did: did.to_string(),
+        alias: alias.map(|a| a.to_string()),
+        method: did.split(':').nth(1).unwrap_or_default().to_string(),
+        cache_hit: resolved.cache_hit,
+        document: serde_json::to_value(&resolved.doc).unwrap_or_default(),
+        peer: did_peer::decode(did).ok(),
+    };
+    (StatusCode::OK, Json(body)).into_response()
+}
+
+// ─── POST /api/messages/send ────────────────────────────────────────────────
+
+pub async fn send_message(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<SendMessageRequest>,
+) -> Response {
+    if req.body.trim().is_empty() && req.attachments.is_empty() {
+        let err = FlowError::Validation("body cannot be empty without attachments".into());
+        return api_error(err, None);
+    }
+
+    let expiry = match (req.no_expiry, req.expires_in) {
+        (true, Some(_)) => {
+            let err = FlowError::Validation("expires_in and no_expiry are exclusive".into());
+            return api_error(err, None);
+        }
+        (true, None) => Expiry::Never,
+        (false, Some(secs)) => Expiry::In(secs),
+        (false, None) => Expiry::Default,
+    };
+    let options = SendOptions {
+        mode: req.mode,
+        anonymous: req.anonymous,
+        reply_to: req.reply_to,
+        thid: req.thid,
+        pthid: req.pthid,
+        expiry,
+        attachments: req.attachments,
+    };
+    match flows::send_message::send_message(&state, &req.from, &req.to, &req.body, &options)
+        .await
+    {
+        Ok(events) => {
+            // The first event is the plaintext message
+            let plaintext = events.first().map(|e| &e.raw_json);
+            let message_id = plaintext.and_then(|m| m.get("id")).cloned();
+            let thid = plaintext
+                .and_then(|m| m.get("thid"))
+                .or(message_id.as_ref())
+                .cloned();
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "status": "stored",
+                    "mode": options.effective_mode().unwrap_or(options.mode),
+                    "anonymous": req.anonymous,
+                    "message_id": message_id,
+                    "thid": thid,
+                    "expires_time": plaintext.and_then(|m| m.get("expires_time")),
+                    "attachments": plaintext
+                        .and_then(|m| m.get("attachments"))
+                        .and_then(serde_json::Value::as_array)
+                        .map_or(0, Vec::len),
+                    "events_count": events.len(),
+                    "correlation_id": events.first().and_then(|e| e.correlation_id.clone()),
+                })),
+            )
+                .into_response()
+        }
         Err(e) => {
             error!("send_message error: {e}");
-            api_error(StatusCode::INTERNAL_SERVER_ERROR, e, Some("send_message"))
+            api_error(e, Some("send_message"))
         }
     }
 }
@@ -117,68 +497,508 @@ pub async fn send_ping(
             .into_response(),
         Err(e) => {
             error!("trust_ping error: {e}");
-            api_error(StatusCode::INTERNAL_SERVER_ERROR, e, Some("trust_ping"))
+            api_error(e, Some("trust_ping"))
+        }
+    }
+}
+
+// ─── POST /api/discover-features ────────────────────────────────────────────
+
+pub async fn discover_features(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<DiscoverFeaturesRequest>,
+) -> Response {
+    let queries = req.queries.unwrap_or_else(|| {
+        vec![FeatureQuery {
+            feature_type: FeatureKind::Protocol,
+            match_: "*".to_string(),
+        }]
+    });
+
+    match flows::discover_features::discover_features(&state, &req.from, &req.to, &queries).await
+    {
+        Ok((events, disclosures)) => (
+            StatusCode::OK,
+            Json(json!({
+                "status": "disclosed",
+                "disclosures": disclosures,
+                "events_count": events.len(),
+                "correlation_id": events.first().and_then(|e| e.correlation_id.clone()),
+            })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("discover_features error: {e}");
+            api_error(e, Some("discover_features"))
+        }
+    }
+}
+
+// ─── POST /api/oob/invitations ──────────────────────────────────────────────
+
+pub async fn create_invitation(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<CreateInvitationRequest>,
+) -> Response {
+    match flows::oob::create_invitation(&state, &req.from, req.goal_code, req.goal).await {
+        Ok((_, invitation)) => (
+            StatusCode::CREATED,
+            Json(json!({
+                "url": invitation.to_url(),
+                "invitation": invitation,
+            })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("create_invitation error: {e}");
+            api_error(e, Some("oob_invitation"))
         }
     }
 }
 
-// ─── GET /api/messages/{did} ────────────────────────────────────────────────
+// ─── POST /api/oob/accept ───────────────────────────────────────────────────
+
+pub async fn accept_invitation(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<AcceptInvitationRequest>,
+) -> Response {
+    let invitation = match flows::oob::parse_invitation(req.invitation, req.url.as_deref()) {
+        Ok(invitation) => invitation,
+        Err(e) => return api_error(e, Some("oob_accept")),
+    };
+
+    match flows::oob::accept_invitation(&state, &req.alias, &invitation).await {
+        Ok((events, inviter_managed)) => (
+            StatusCode::OK,
+            Json(json!({
+                "status": "connected",
+                "invitation_id": invitation.id,
+                "inviter": invitation.from,
+                "inviter_managed": inviter_managed,
+                "events_count": events.len(),
+                "correlation_id": events.last().and_then(|e| e.correlation_id.clone()),
+            })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("accept_invitation error: {e}");
+            api_error(e, Some("oob_accept"))
+        }
+    }
+}
+
+// ─── GET /api/messages/{alias} ──────────────────────────────────────────────
 
 pub async fn fetch_messages(
     State(state): State<Arc<AppState>>,
     axum::extract::Path(alias): axum::extract::Path<String>,
+    axum::extract::Query(query): axum::extract::Query<FetchQuery>,
 ) -> Response {
     use affinidi_messaging_sdk::messages::{FetchDeletePolicy, fetch::FetchOptions};
 
-    let profile = match alias.to_lowercase().as_str() {
-        "alice" => &state.alice_profile,
-        "bob" => &state.bob_profile,
-        _ => return api_error(StatusCode::BAD_REQUEST, format!("Unknown alias: {alias}"), None),
+    let Some(identity) = state.identity(&alias).await else {
+        return api_error(FlowError::unknown_alias("alias", &alias), None);
     };
 
+    let limit = query.limit.unwrap_or(DEFAULT_FETCH_LIMIT);
+    if !(1..=MAX_FETCH_LIMIT).contains(&limit) {
+        return api_error(
+            FlowError::Validation(format!("limit must be between 1 and {MAX_FETCH_LIMIT}")),
+            Some("fetch_messages"),
+        );
+    }
     let fetch_opts = FetchOptions {
-        limit: 50,
-        delete_policy: FetchDeletePolicy::DoNotDelete,
-        start_id: None,
+        limit,
+        delete_policy: match query.delete_policy {
+            DeletePolicy::DoNotDelete => FetchDeletePolicy::DoNotDelete,
+            DeletePolicy::DeleteOnRetrieve => FetchDeletePolicy::Optimistic,
+        },
+        start_id: query.start_id.clone(),
     };
 
-    match state.atm.fetch_messages(profile, &fetch_opts).await {
+    match state.atm.fetch_messages(&identity.profile, &fetch_opts).await {
         Ok(response) => {
-            let messages: Vec<serde_json::Value> = response
-                .success
-                .iter()
-                .map(|m| {
-                    json!({
-                        "msg_id": m.msg_id,
-                        "msg": m.msg,
-                    })
-                })
-                .collect();
-            (StatusCode::OK, Json(json!({ "messages": messages }))).into_response()
+            let mut messages: Vec<serde_json::Value> = Vec::with_capacity(response.success.len());
+            for m in &response.success {
+                let mut entry = json!({
+                    "msg_id": m.msg_id,
+                    "receive_id": m.receive_id,
+                    "timestamp": m.timestamp,
+                    "size": m.size,
+                    "msg": m.msg,
+                });
+                let Some(packed) = &m.msg else {
+                    messages.push(entry);
+                    continue;
+                };
+                match state.atm.unpack(packed).await {
+                    Ok((message, metadata)) => {
+                        entry["message"] = serde_json::to_value(&message)
+                            .unwrap_or_else(|_| json!({"id": message.id}));
+                        entry["security"] = json!({
+                            "encrypted": metadata.encrypted,
+                            "authenticated": metadata.authenticated,
+                            "non_repudiation": metadata.non_repudiation,
+                            "anonymous_sender": metadata.anonymous_sender,
+                            "sender_kid": metadata
+                                .encrypted_from_kid
+                                .as_ref()
+                                .or(metadata.sign_from.as_ref()),
+                        });
+                        entry["expired"] =
+                            json!(flows::problem_report::is_expired(&message));
+                        entry["unpack_metadata"] = json!(metadata);
+                    }
+                    Err(e) => entry["unpack_error"] = json!(e.to_string()),
+                }
+                messages.push(entry);
+            }
+
+            // A full page of undeleted messages may have more behind it
+            let next_cursor = match query.delete_policy {
+                DeletePolicy::DoNotDelete if response.success.len() == limit => {
+                    response.success.last().and_then(|m| m.receive_id.clone())
+                }
+                _ => None,
+            };
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "messages": messages,
+                    "next_cursor": next_cursor,
+                    "delete_policy": query.delete_policy,
+                    "get_errors": response.get_errors,
+                    "delete_errors": response.delete_errors,
+                    "listener": listener_status(&state, &alias),
+                })),
+            )
+                .into_response()
         }
         Err(e) => {
             error!("fetch_messages error: {e}");
-            api_error(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("{e}"),
-                Some("fetch_messages"),
+            api_error(FlowError::from_atm("fetch_messages failed", e), Some("fetch_messages"))
+        }
+    }
+}
+
+/// Whether `alias`'s inbound listener is draining its queue, for the queue
+/// endpoints' responses: while it runs they usually find nothing.
+fn listener_status(state: &AppState, alias: &str) -> serde_json::Value {
+    let paused = state.inbound.is_paused(alias);
+    json!({
+        "paused": paused,
+        "note": (!paused).then(|| format!(
+            "The inbound listener deletes messages as they arrive; pause it with \
+             PUT /api/identities/{alias}/listener to keep them queued"
+        )),
+    })
+}
+
+// ─── DELETE /api/messages/{alias} ───────────────────────────────────────────
+
+pub async fn delete_messages(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(alias): axum::extract::Path<String>,
+    Json(req): Json<DeleteMessagesRequest>,
+) -> Response {
+    match flows::delete_messages::delete_messages(&state, &alias, &req.message_ids).await {
+        Ok((events, results)) => (
+            StatusCode::OK,
+            Json(json!({
+                "deleted": results.iter().filter(|r| r.deleted).count(),
+                "failed": results.iter().filter(|r| !r.deleted).count(),
+                "results": results,
+                "events_count": events.len(),
+                "correlation_id": events.first().and_then(|e| e.correlation_id.clone()),
+                "listener": listener_status(&state, &alias),
+            })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("delete_messages error: {e}");
+            api_error(e, Some("delete_messages"))
+        }
+    }
+}
+
+// ─── POST /api/pickup/{alias} ───────────────────────────────────────────────
+
+pub async fn pickup_messages(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(alias): axum::extract::Path<String>,
+    req: Option<Json<PickupRequest>>,
+) -> Response {
+    let req = req.map(|Json(r)| r).unwrap_or_default();
+    let limit = req.limit.unwrap_or(flows::message_pickup::DEFAULT_LIMIT);
+    let acknowledge = req.acknowledge.unwrap_or(true);
+
+    match flows::message_pickup::pickup(&state, &alias, limit, acknowledge).await {
+        Ok(result) => (
+            StatusCode::OK,
+            Json(json!({
+                "status": result.status,
+                "messages": result.messages,
+                "acknowledged": result.acknowledged,
+                "events_count": result.events.len(),
+                "correlation_id": result.events.first().and_then(|e| e.correlation_id.clone()),
+                "listener": listener_status(&state, &alias),
+            })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("pickup error: {e}");
+            api_error(e, Some("message_pickup"))
+        }
+    }
+}
+
+// ─── GET /api/threads/{id} ──────────────────────────────────────────────────
+
+pub async fn get_thread(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> Response {
+    // Accept any message ID as well as the thread ID itself
+    let thid = state.threads.thread_of(&id).unwrap_or(id);
+    let messages = state.threads.thread(&thid);
+    let children = state.threads.children(&thid);
+    if messages.is_empty() && children.is_empty() {
+        let err = FlowError::NotFound(format!("No messages recorded for thread {thid}"));
+        return api_error(err, None);
+    }
+    let metadata = match state.store.clone() {
+        Some(store) => {
+            let thid = thid.clone();
+            tokio::task::spawn_blocking(move || store.thread(&thid))
+                .await
+                .ok()
+                .flatten()
+        }
+        None => None,
+    };
+    Json(json!({
+        "thid": thid,
+        "metadata": metadata,
+        "messages": messages,
+        "child_threads": children,
+    }))
+    .into_response()
+}
+
+// ─── POST /api/files/send ───────────────────────────────────────────────────
+
+pub async fn send_file(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Query(query): axum::extract::Query<SendFileQuery>,
+    headers: axum::http::HeaderMap,
+    body: axum::body::Bytes,
+) -> Response {
+    let media_type = headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream");
+    let upload = flows::file_transfer::FileUpload {
+        filename: query.filename.unwrap_or_else(|| "upload.bin".to_string()),
+        media_type: media_type.to_string(),
+        data: body.to_vec(),
+        chunk_size: query.chunk_size,
+    };
+    match flows::file_transfer::send_file(&state, &query.from, &query.to, &upload).await {
+        Ok((events, report)) => (
+            StatusCode::OK,
+            Json(json!({
+                "status": "sent",
+                "transfer": report.transfer,
+                "envelope_bytes": report.envelope_bytes,
+                "max_envelope_bytes": report.max_envelope_bytes,
+                "events_count": events.len(),
+                "correlation_id": events.first().and_then(|e| e.correlation_id.clone()),
+            })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("send_file error: {e}");
+            api_error(e, Some("send_file"))
+        }
+    }
+}
+
+// ─── GET /api/files ─────────────────────────────────────────────────────────
+
+pub async fn get_transfers(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(json!({ "transfers": state.transfers.list() }))
+}
+
+// ─── GET /api/files/{alias}/{transfer_id} ───────────────────────────────────
+
+pub async fn download_file(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path((alias, transfer_id)): axum::extract::Path<(String, String)>,
+) -> Response {
+    let Some((transfer, data)) = state.transfers.get(&alias, &transfer_id) else {
+        let err = FlowError::NotFound(format!("No transfer {transfer_id} for {alias}"));
+        return api_error(err, None);
+    };
+    match (data, transfer.manifest) {
+        (Some(data), Some(manifest)) => {
+            let disposition = format!(
+                "attachment; filename=\"{}\"",
+                manifest.filename.replace('"', "")
+            );
+            (
+                StatusCode::OK,
+                [
+                    (axum::http::header::CONTENT_TYPE, manifest.media_type),
+                    (axum::http::header::CONTENT_DISPOSITION, disposition),
+                ],
+                data,
             )
+                .into_response()
+        }
+        _ => {
+            let err = FlowError::Validation(format!(
+                "Transfer {transfer_id} has no reassembled file (status {:?})",
+                transfer.status
+            ));
+            api_error(err, None)
         }
     }
 }
 
-// ─── GET /api/packets/stream (SSE) ─────────────────────────────────────────
+// ─── GET /api/packets ───────────────────────────────────────────────────────
+
+pub async fn get_packets<S: PacketSource>(
+    State(state): State<Arc<S>>,
+    axum::extract::Query(filter): axum::extract::Query<PacketFilter>,
+) -> Json<serde_json::Value> {
+    let events = state.packet_log().query(&filter);
+    Json(json!({ "count": events.len(), "events": events }))
+}
 
-pub async fn packet_stream(
+// ─── GET /api/packets/export ────────────────────────────────────────────────
+
+pub async fn export_packets(State(state): State<Arc<AppState>>) -> Response {
+    let identities = state
+        .identities
+        .read()
+        .await
+        .infos()
+        .into_values()
+        .collect();
+    let packets = state.packet_tx.query(&PacketFilter::default());
+    let archive = SessionArchive::new(identities, packets);
+    let filename = format!(
+        "session-{}.jsonl",
+        chrono::Utc::now().format("%Y%m%dT%H%M%SZ")
+    );
+    (
+        StatusCode::OK,
+        [
+            (
+                axum::http::header::CONTENT_TYPE,
+                "application/x-ndjson".to_string(),
+            ),
+            (
+                axum::http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        archive.to_jsonl(),
+    )
+        .into_response()
+}
+
+// ─── POST /api/packets/import ───────────────────────────────────────────────
+
+pub async fn import_packets(
     State(state): State<Arc<AppState>>,
+    axum::extract::Query(query): axum::extract::Query<ImportQuery>,
+    body: String,
+) -> Response {
+    let archive = match SessionArchive::parse(&body) {
+        Ok(archive) => archive,
+        Err(e) => {
+            return api_error(FlowError::Validation(format!("invalid archive: {e}")), None);
+        }
+    };
+
+    if !query.append {
+        state.packet_tx.clear();
+        if let Some(store) = &state.store {
+            store.clear_packets();
+        }
+        let _ = state.packet_tx.notify(PacketEvent::reset());
+    }
+    let total = archive.packets.len();
+    let imported = state.packet_tx.import(archive.packets);
+    (
+        StatusCode::OK,
+        Json(json!({
+            "status": "imported",
+            "packets": imported,
+            "duplicates": total - imported,
+            "correlations": archive.correlations.len(),
+            "identities": archive.identities,
+        })),
+    )
+        .into_response()
+}
+
+// ─── GET /api/packets/stream (SSE) ─────────────────────────────────────────
+
+/// Per-connection stream state: events to replay, then the live receiver.
+struct PacketCursor<S> {
+    state: Arc<S>,
+    rx: broadcast::Receiver<LoggedPacket>,
+    backlog: VecDeque<LoggedPacket>,
+    last_seq: Option<u64>,
+}
+
+pub async fn packet_stream<S: PacketSource>(
+    State(state): State<Arc<S>>,
+    axum::extract::Query(query): axum::extract::Query<PacketStreamQuery>,
+    headers: axum::http::HeaderMap,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    let rx = state.packet_tx.subscribe();
-    let stream = BroadcastStream::new(rx).filter_map(|result| match result {
-        Ok(event) => {
-            let data = serde_json::to_string(&event).unwrap_or_default();
-            Some(Ok(Event::default().data(data).event("packet")))
+    // Browsers send Last-Event-ID when they reconnect
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let replay = match last_event_id {
+        Some(id) => Replay::After(id),
+        None if query.history => Replay::All,
+        None => Replay::None,
+    };
+    let (backlog, rx) = state.packet_log().subscribe(replay);
+    let cursor = PacketCursor {
+        state,
+        rx,
+        backlog: backlog.into(),
+        last_seq: None,
+    };
+
+    let stream = futures::stream::unfold(cursor, |mut cursor| async move {
+        loop {
+            if let Some(packet) = cursor.backlog.pop_front() {
+                cursor.last_seq = Some(packet.seq);
+                let data = serde_json::to_string(&packet.event).unwrap_or_default();
+                let event = Event::default()
+                    .id(&packet.event.id)
+                    .data(data)
+                    .event("packet");
+                return Some((Ok(event), cursor));
+            }
+            match cursor.rx.recv().await {
+                Ok(packet) if cursor.last_seq.is_some_and(|seq| packet.seq <= seq) => {}
+                Ok(packet) => cursor.backlog.push_back(packet),
+                // Fell behind the channel — catch up from the history
+                Err(RecvError::Lagged(_)) => {
+                    let missed = cursor.state.packet_log().after(cursor.last_seq);
+                    cursor.backlog.extend(missed);
+                }
+                Err(RecvError::Closed) => return None,
+            }
         }
-        Err(_) => None, // lagged receiver — skip
     });
     Sse::new(stream).keep_alive(
         axum::response::sse::KeepAlive::new()
@@ -192,16 +1012,59 @@ pub async fn packet_stream(
 pub async fn reset_demo(
     State(state): State<Arc<AppState>>,
 ) -> impl IntoResponse {
+    // Deactivate identities created at runtime — their secrets only live in
+    // memory, so their mediator accounts are deleted too
+    let runtime_aliases: Vec<String> = state
+        .identities
+        .read()
+        .await
+        .all()
+        .filter(|i| i.runtime)
+        .map(|i| i.alias().to_string())
+        .collect();
+    let mut removed = Vec::new();
+    for alias in runtime_aliases {
+        if let Some(removal) = state.remove_identity(&alias, true).await {
+            removed.push(removal);
+        }
+    }
+    state.invitations.write().await.clear();
+    state.threads.clear();
+    state.transfers.clear();
+    state.packet_tx.clear();
+    if let Some(store) = &state.store {
+        store.truncate();
+    }
+
     // Emit a special "reset" event so the frontend clears its state
-    use crate::packet_logger::{PacketDirection, PacketEvent, PacketStep};
-    let evt = PacketEvent::new(
-        PacketDirection::Outbound,
-        "system",
-        "all",
-        PacketStep::PlaintextMessage,
-        json!({ "action": "reset" }),
-        None,
-    );
-    let _ = state.packet_tx.send(evt);
-    Json(json!({ "status": "reset" }))
+    let _ = state.packet_tx.notify(PacketEvent::reset());
+    Json(json!({ "status": "reset", "removed_identities": removed }))
+}
+
+// ─── Replay mode ────────────────────────────────────────────────────────────
+
+pub async fn get_replay_identities(State(state): State<Arc<ReplayState>>) -> impl IntoResponse {
+    let identities: IdentitiesResponse = state.identities.clone();
+    Json(identities)
+}
+
+/// `POST /api/reset` in replay mode: play the archive again from the start.
+pub async fn restart_replay(State(state): State<Arc<ReplayState>>) -> impl IntoResponse {
+    state.restart();
+    Json(json!({
+        "status": "replaying",
+        "packets": state.packets.len(),
+        "speed": state.speed,
+    }))
+}
+
+/// Every other `/api` route in replay mode.
+pub async fn replay_unavailable(
+    axum::extract::OriginalUri(uri): axum::extract::OriginalUri,
+) -> Response {
+    let err = FlowError::Unavailable(format!(
+        "{} needs a mediator; the server is replaying a recorded session",
+        uri.path()
+    ));
+    api_error(err, None)
 }
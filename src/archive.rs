@@ -0,0 +1,270 @@
+/// JSONL session archives — a self-describing capture of a demo session that
+/// can be loaded back into the packet inspector without a mediator.
+///
+/// Each line is one JSON record tagged by `record`:
+///
+/// 1. `header`: format name, version, export time and record counts.
+/// 2. `identity`: public DID info for every identity (no secrets).
+/// 3. `packet`: every `PacketEvent`, oldest first.
+/// 4. `correlation`: the packet IDs sharing a correlation ID, with their steps and time span.
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::identity::IdentityInfo;
+use crate::packet_logger::{PacketEvent, PacketStep};
+
+pub const FORMAT: &str = "didcomm-demo-session";
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Header {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: String,
+    pub identities: usize,
+    pub packets: usize,
+    pub correlations: usize,
+}
+
+/// Events that share a correlation ID, i.e. belong to one flow.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Correlation {
+    pub correlation_id: String,
+    pub packet_ids: Vec<String>,
+    pub steps: Vec<PacketStep>,
+    pub first_timestamp: String,
+    pub last_timestamp: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum Record {
+    Header(Header),
+    Identity(IdentityInfo),
+    Packet(Box<PacketEvent>),
+    Correlation(Correlation),
+}
+
+/// A session: identities plus the packet events they produced.
+#[derive(Debug, Default)]
+pub struct SessionArchive {
+    pub identities: Vec<IdentityInfo>,
+    pub packets: Vec<PacketEvent>,
+    pub correlations: Vec<Correlation>,
+}
+
+impl SessionArchive {
+    /// Build an archive, grouping `packets` by correlation ID. Reset markers
+    /// are control events, not traffic, and are left out.
+    pub fn new(identities: Vec<IdentityInfo>, mut packets: Vec<PacketEvent>) -> Self {
+        packets.retain(|packet| !packet.is_reset());
+        let mut groups: BTreeMap<&str, Vec<&PacketEvent>> = BTreeMap::new();
+        for packet in &packets {
+            if let Some(correlation_id) = &packet.correlation_id {
+                groups.entry(correlation_id).or_default().push(packet);
+            }
+        }
+        let correlations = groups
+            .into_iter()
+            .map(|(correlation_id, group)| Correlation {
+                correlation_id: correlation_id.to_string(),
+                packet_ids: group.iter().map(|p| p.id.clone()).collect(),
+                steps: group.iter().map(|p| p.step.clone()).collect(),
+                first_timestamp: group[0].timestamp.clone(),
+                last_timestamp: group[group.len() - 1].timestamp.clone(),
+            })
+            .collect();
+        Self {
+            identities,
+            packets,
+            correlations,
+        }
+    }
+
+    pub fn to_jsonl(&self) -> String {
+        let header = Header {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            exported_at: Utc::now().to_rfc3339(),
+            identities: self.identities.len(),
+            packets: self.packets.len(),
+            correlations: self.correlations.len(),
+        };
+        let records = std::iter::once(Record::Header(header))
+            .chain(self.identities.iter().cloned().map(Record::Identity))
+            .chain(
+                self.packets
+                    .iter()
+                    .cloned()
+                    .map(|p| Record::Packet(Box::new(p))),
+            )
+            .chain(self.correlations.iter().cloned().map(Record::Correlation));
+
+        let mut out = String::new();
+        for record in records {
+            out.push_str(&serde_json::to_string(&record).unwrap_or_default());
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Parse a JSONL archive. The first record must be a header for a
+    /// supported version; blank lines are ignored.
+    pub fn parse(jsonl: &str) -> Result<Self, String> {
+        let mut lines = jsonl
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+
+        let Some((_, first)) = lines.next() else {
+            return Err("archive is empty".into());
+        };
+        let header = match serde_json::from_str(first) {
+            Ok(Record::Header(header)) => header,
+            _ => return Err("first line is not a session archive header".into()),
+        };
+        if header.format != FORMAT || header.version > VERSION {
+            return Err(format!(
+                "unsupported archive {} v{} (expected {FORMAT} v{VERSION})",
+                header.format, header.version
+            ));
+        }
+
+        let mut archive = Self::default();
+        for (n, line) in lines {
+            match serde_json::from_str(line) {
+                Ok(Record::Header(_)) => return Err(format!("line {}: repeated header", n + 1)),
+                Ok(Record::Identity(identity)) => archive.identities.push(identity),
+                Ok(Record::Packet(packet)) => archive.packets.push(*packet),
+                Ok(Record::Correlation(correlation)) => archive.correlations.push(correlation),
+                Err(e) => return Err(format!("line {}: {e}", n + 1)),
+            }
+        }
+        Ok(archive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::packet_logger::PacketDirection;
+
+    fn identity(alias: &str) -> IdentityInfo {
+        IdentityInfo {
+            alias: alias.into(),
+            did: format!("did:peer:2.{alias}"),
+            mediator_did: None,
+            key_types: vec!["Ed25519".into()],
+            verification_methods: Vec::new(),
+            authentication: Vec::new(),
+            key_agreement: Vec::new(),
+            services: Vec::new(),
+        }
+    }
+
+    fn packet(step: PacketStep, correlation_id: Option<&str>) -> PacketEvent {
+        PacketEvent::new(
+            PacketDirection::Outbound,
+            "did:peer:2.alice",
+            "did:peer:2.bob",
+            step,
+            json!({"id": "msg-1"}),
+            correlation_id.map(str::to_string),
+        )
+    }
+
+    fn sample() -> SessionArchive {
+        SessionArchive::new(
+            vec![identity("alice"), identity("bob")],
+            vec![
+                packet(PacketStep::TrustPing, Some("c1")),
+                packet(PacketStep::MediatorAck, Some("c1")),
+                packet(PacketStep::PlaintextMessage, None),
+            ],
+        )
+    }
+
+    #[test]
+    fn leaves_out_reset_markers() {
+        let archive = SessionArchive::new(
+            Vec::new(),
+            vec![
+                PacketEvent::reset(),
+                packet(PacketStep::TrustPing, Some("c1")),
+            ],
+        );
+        assert_eq!(archive.packets.len(), 1);
+        assert_eq!(archive.packets[0].step, PacketStep::TrustPing);
+    }
+
+    #[test]
+    fn round_trips_through_jsonl() {
+        let archive = sample();
+        let parsed = SessionArchive::parse(&archive.to_jsonl()).unwrap();
+
+        let aliases: Vec<_> = parsed.identities.iter().map(|i| i.alias.as_str()).collect();
+        assert_eq!(aliases, ["alice", "bob"]);
+        let ids: Vec<_> = parsed.packets.iter().map(|p| &p.id).collect();
+        assert_eq!(
+            ids,
+            archive.packets.iter().map(|p| &p.id).collect::<Vec<_>>()
+        );
+        assert_eq!(parsed.correlations.len(), 1);
+        assert_eq!(parsed.correlations[0].correlation_id, "c1");
+        assert_eq!(
+            parsed.correlations[0].steps,
+            [PacketStep::TrustPing, PacketStep::MediatorAck]
+        );
+    }
+
+    #[test]
+    fn ignores_blank_lines() {
+        let jsonl = sample().to_jsonl().replace('\n', "\n\n");
+        assert_eq!(SessionArchive::parse(&jsonl).unwrap().packets.len(), 3);
+    }
+
+    #[test]
+    fn rejects_empty_archives_and_missing_headers() {
+        assert_eq!(
+            SessionArchive::parse("\n \n").unwrap_err(),
+            "archive is empty"
+        );
+        let jsonl = sample().to_jsonl();
+        let without_header = jsonl.split_once('\n').unwrap().1;
+        assert!(
+            SessionArchive::parse(without_header)
+                .unwrap_err()
+                .contains("not a session archive header")
+        );
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let jsonl =
+            sample()
+                .to_jsonl()
+                .replacen(&format!("\"version\":{VERSION}"), "\"version\":99", 1);
+        assert!(SessionArchive::parse(&jsonl).unwrap_err().contains("v99"));
+    }
+
+    #[test]
+    fn reports_the_line_of_a_malformed_record() {
+        let mut jsonl = sample().to_jsonl();
+        jsonl.push_str("{\"record\":\"packet\",\"id\":\n");
+        let err = SessionArchive::parse(&jsonl).unwrap_err();
+        assert!(err.starts_with("line 8:"), "{err}");
+
+        let mut jsonl = sample().to_jsonl();
+        let header = jsonl.lines().next().unwrap().to_string();
+        jsonl.push_str(&header);
+        assert!(
+            SessionArchive::parse(&jsonl)
+                .unwrap_err()
+                .contains("repeated header")
+        );
+    }
+}
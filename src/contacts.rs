@@ -0,0 +1,199 @@
+/// Per-identity contact records — the peers each identity has heard from,
+/// keyed by their current DID.
+///
+/// A validated `from_prior` rotation moves a contact to its new DID and keeps
+/// the old one in `previous_dids`, so later messages from either are attributed
+/// to the same peer.
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Contact {
+    /// The peer's current DID.
+    pub did: String,
+    pub alias: Option<String>,
+    /// DIDs the peer rotated away from, oldest first.
+    pub previous_dids: Vec<String>,
+    pub last_seen: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotated_at: Option<String>,
+}
+
+/// Contact lists keyed by lower-cased owner alias.
+#[derive(Default)]
+pub struct ContactBook {
+    owners: Mutex<HashMap<String, Vec<Contact>>>,
+}
+
+impl ContactBook {
+    /// Record that `owner` heard from `did` (current or previous DID of a contact).
+    pub fn seen(&self, owner: &str, did: &str, alias: Option<&str>) {
+        let mut owners = self.owners.lock().unwrap();
+        let contacts = owners.entry(owner.to_lowercase()).or_default();
+        let now = Utc::now().to_rfc3339();
+        match contacts
+            .iter_mut()
+            .find(|c| c.did == did || c.previous_dids.iter().any(|p| p == did))
+        {
+            Some(contact) => {
+                contact.last_seen = now;
+                if contact.alias.is_none() {
+                    contact.alias = alias.map(str::to_string);
+                }
+            }
+            None => contacts.push(Contact {
+                did: did.to_string(),
+                alias: alias.map(str::to_string),
+                previous_dids: Vec::new(),
+                last_seen: now,
+                rotated_at: None,
+            }),
+        }
+    }
+
+    /// Move `owner`'s contact from `old_did` to `new_did`, creating it if
+    /// `old_did` was never seen. Returns the updated record and whether the
+    /// old DID was already known, or `None` if the rotation was applied before
+    /// (every later message repeats the `from_prior`); then only `last_seen`
+    /// is refreshed.
+    pub fn rotate(
+        &self,
+        owner: &str,
+        old_did: &str,
+        new_did: &str,
+        alias: Option<&str>,
+    ) -> Option<(Contact, bool)> {
+        let mut owners = self.owners.lock().unwrap();
+        let contacts = owners.entry(owner.to_lowercase()).or_default();
+        let now = Utc::now().to_rfc3339();
+
+        if let Some(contact) = contacts
+            .iter_mut()
+            .find(|c| c.did == new_did && c.previous_dids.iter().any(|p| p == old_did))
+        {
+            contact.last_seen = now;
+            return None;
+        }
+
+        // The new DID may already have been recorded by `seen`
+        contacts.retain(|c| c.did != new_did || c.did == old_did);
+        let known = contacts.iter().any(|c| c.did == old_did);
+        if !known {
+            contacts.push(Contact {
+                did: old_did.to_string(),
+                alias: None,
+                previous_dids: Vec::new(),
+                last_seen: now.clone(),
+                rotated_at: None,
+            });
+        }
+        let contact = contacts
+            .iter_mut()
+            .find(|c| c.did == old_did)
+            .expect("contact inserted above");
+        contact.previous_dids.push(old_did.to_string());
+        contact.did = new_did.to_string();
+        contact.last_seen = now.clone();
+        contact.rotated_at = Some(now);
+        if let Some(alias) = alias {
+            contact.alias = Some(alias.to_string());
+        }
+        Some((contact.clone(), known))
+    }
+
+    /// True if `did` is the current DID of one of `owner`'s contacts.
+    pub fn knows(&self, owner: &str, did: &str) -> bool {
+        self.owners
+            .lock()
+            .unwrap()
+            .get(&owner.to_lowercase())
+            .is_some_and(|contacts| contacts.iter().any(|c| c.did == did))
+    }
+
+    /// Every contact of `owner`.
+    pub fn list(&self, owner: &str) -> Vec<Contact> {
+        self.owners
+            .lock()
+            .unwrap()
+            .get(&owner.to_lowercase())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Forget `owner`'s contact list (identity removed).
+    pub fn remove_owner(&self, owner: &str) {
+        self.owners.lock().unwrap().remove(&owner.to_lowercase());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seen_records_each_did_once() {
+        let book = ContactBook::default();
+        book.seen("Alice", "did:peer:2.bob", None);
+        book.seen("alice", "did:peer:2.bob", Some("bob"));
+        book.seen("alice", "did:peer:2.carol", Some("carol"));
+
+        let contacts = book.list("ALICE");
+        assert_eq!(contacts.len(), 2);
+        assert_eq!(contacts[0].did, "did:peer:2.bob");
+        assert_eq!(contacts[0].alias.as_deref(), Some("bob"));
+        assert!(contacts[0].previous_dids.is_empty());
+    }
+
+    #[test]
+    fn rotation_moves_the_contact_to_the_new_did() {
+        let book = ContactBook::default();
+        book.seen("alice", "did:peer:2.old", Some("bob"));
+
+        let (contact, known) = book
+            .rotate("alice", "did:peer:2.old", "did:peer:2.new", None)
+            .unwrap();
+        assert!(known);
+        assert_eq!(contact.did, "did:peer:2.new");
+        assert_eq!(contact.previous_dids, ["did:peer:2.old"]);
+        assert_eq!(contact.alias.as_deref(), Some("bob"));
+        assert!(contact.rotated_at.is_some());
+
+        // Messages from the old DID are still attributed to the same contact
+        book.seen("alice", "did:peer:2.old", None);
+        assert_eq!(book.list("alice").len(), 1);
+    }
+
+    #[test]
+    fn rotation_from_an_unknown_did_creates_the_contact() {
+        let book = ContactBook::default();
+        // The new DID may have been seen before the rotation was validated
+        book.seen("alice", "did:peer:2.new", None);
+
+        let (contact, known) = book
+            .rotate("alice", "did:peer:2.old", "did:peer:2.new", Some("bob"))
+            .unwrap();
+        assert!(!known);
+        assert_eq!(contact.previous_dids, ["did:peer:2.old"]);
+        assert_eq!(book.list("alice").len(), 1);
+    }
+
+    #[test]
+    fn repeated_rotation_keeps_the_rotated_contact() {
+        let book = ContactBook::default();
+        book.seen("alice", "did:peer:2.old", Some("bob"));
+        book.rotate("alice", "did:peer:2.old", "did:peer:2.new", None);
+
+        assert!(
+            book.rotate("alice", "did:peer:2.old", "did:peer:2.new", None)
+                .is_none()
+        );
+        let contacts = book.list("alice");
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].did, "did:peer:2.new");
+        assert_eq!(contacts[0].previous_dids, ["did:peer:2.old"]);
+        assert_eq!(contacts[0].alias.as_deref(), Some("bob"));
+    }
+}
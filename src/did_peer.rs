@@ -0,0 +1,212 @@
+/// did:peer decoder — breaks a numalgo 2 DID string into its segments so
+/// the frontend can show what each `.V…`, `.E…` and `.S…` part encodes.
+///
+/// Key IDs follow the same `#key-N` numbering the resolver uses.
+use affinidi_tdk::did_common::{PeerPurpose, PeerService};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::identity::multikey_type;
+
+/// A decoded did:peer string.
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerDidDecoded {
+    pub numalgo: u8,
+    pub segments: Vec<PeerSegment>,
+}
+
+/// One `.`-separated segment of the method-specific identifier.
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerSegment {
+    /// Purpose prefix character ('V', 'E', 'S', ...).
+    pub code: char,
+    /// Human-readable purpose ("authentication", "keyAgreement", "service", ...).
+    pub purpose: &'static str,
+    /// The raw segment as it appears in the DID (without the purpose code).
+    pub value: String,
+    #[serde(flatten)]
+    pub detail: PeerSegmentDetail,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PeerSegmentDetail {
+    Key {
+        key_id: String,
+        key_type: Option<&'static str>,
+    },
+    Service {
+        /// Abbreviated JSON as encoded in the DID (`t`, `s`, `a`, `r`).
+        abbreviated: Value,
+        /// Expanded DID document service entry.
+        expanded: Value,
+    },
+}
+
+/// Decode a did:peer string. Returns an error for other methods and numalgos.
+pub fn decode(did: &str) -> Result<PeerDidDecoded, String> {
+    let identifier = did
+        .strip_prefix("did:peer:")
+        .ok_or_else(|| format!("Not a did:peer: {did}"))?;
+
+    match identifier.chars().next() {
+        Some('2') => decode_numalgo_2(did, &identifier[1..]),
+        Some(c) => Err(format!("Unsupported did:peer numalgo '{c}'")),
+        None => Err("Empty did:peer identifier".to_string()),
+    }
+}
+
+fn decode_numalgo_2(did: &str, content: &str) -> Result<PeerDidDecoded, String> {
+    let mut segments = Vec::new();
+    let mut key_count = 0;
+    let mut service_idx = 0;
+
+    for part in content.split('.').filter(|s| !s.is_empty()) {
+        let code = part.chars().next().unwrap_or_default();
+        let purpose =
+            PeerPurpose::from_char(code).ok_or_else(|| format!("Invalid purpose code '{code}'"))?;
+
+        if purpose == PeerPurpose::Service {
+            let service =
+                PeerService::decode(part).map_err(|e| format!("Service decode error: {e}"))?;
+            let expanded = service
+                .to_did_service(did, service_idx)
+                .map_err(|e| format!("Service conversion error: {e}"))?;
+            service_idx += 1;
+
+            segments.push(PeerSegment {
+                code,
+                purpose: purpose_name(purpose),
+                value: part[1..].to_string(),
+                detail: PeerSegmentDetail::Service {
+                    abbreviated: serde_json::to_value(&service).unwrap_or(Value::Null),
+                    expanded: serde_json::to_value(&expanded).unwrap_or(Value::Null),
+                },
+            });
+        } else {
+            key_count += 1;
+            segments.push(PeerSegment {
+                code,
+                purpose: purpose_name(purpose),
+                value: part[1..].to_string(),
+                detail: PeerSegmentDetail::Key {
+                    key_id: format!("{did}#key-{key_count}"),
+                    key_type: multikey_type(&part[1..]),
+                },
+            });
+        }
+    }
+
+    Ok(PeerDidDecoded {
+        numalgo: 2,
+        segments,
+    })
+}
+
+/// Map a did:peer purpose code to its DID document verification relationship.
+fn purpose_name(purpose: PeerPurpose) -> &'static str {
+    match purpose {
+        PeerPurpose::Assertion => "assertionMethod",
+        PeerPurpose::Delegation => "capabilityDelegation",
+        PeerPurpose::Encryption => "keyAgreement",
+        PeerPurpose::Invocation => "capabilityInvocation",
+        PeerPurpose::Verification => "authentication",
+        PeerPurpose::Service => "service",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+    use serde_json::json;
+
+    use super::*;
+
+    const ED25519: &str = "z6MkqRYqQiSgvZQdnBytw86Qbs2ZWUkGv22od935YF4s8M7V";
+    const X25519: &str = "z6LSbysY2xFMRpGMhb7tFTLMpeuPRaqaWM1yECx2AtzE3KCc";
+
+    fn service(uri: &str) -> String {
+        let abbreviated = json!({"t": "dm", "s": {"uri": uri, "a": ["didcomm/v2"]}});
+        format!("S{}", URL_SAFE_NO_PAD.encode(abbreviated.to_string()))
+    }
+
+    #[test]
+    fn decodes_every_key_purpose() {
+        let did = format!("did:peer:2.V{ED25519}.E{X25519}.A{ED25519}.I{ED25519}.D{ED25519}");
+        let decoded = decode(&did).unwrap();
+        assert_eq!(decoded.numalgo, 2);
+
+        let expected = [
+            ('V', "authentication", ED25519, "Ed25519"),
+            ('E', "keyAgreement", X25519, "X25519"),
+            ('A', "assertionMethod", ED25519, "Ed25519"),
+            ('I', "capabilityInvocation", ED25519, "Ed25519"),
+            ('D', "capabilityDelegation", ED25519, "Ed25519"),
+        ];
+        assert_eq!(decoded.segments.len(), expected.len());
+        for (n, (segment, (code, purpose, value, key_type))) in
+            decoded.segments.iter().zip(expected).enumerate()
+        {
+            assert_eq!(segment.code, code);
+            assert_eq!(segment.purpose, purpose);
+            assert_eq!(segment.value, value);
+            let PeerSegmentDetail::Key {
+                key_id,
+                key_type: decoded_type,
+            } = &segment.detail
+            else {
+                panic!("segment {code} is not a key");
+            };
+            assert_eq!(key_id, &format!("{did}#key-{}", n + 1));
+            assert_eq!(*decoded_type, Some(key_type));
+        }
+    }
+
+    #[test]
+    fn decodes_services_without_counting_them_as_keys() {
+        let did = format!(
+            "did:peer:2.V{ED25519}.{}.E{X25519}.{}",
+            service("https://mediator.example/one"),
+            service("https://mediator.example/two")
+        );
+        let decoded = decode(&did).unwrap();
+        let codes: Vec<char> = decoded.segments.iter().map(|s| s.code).collect();
+        assert_eq!(codes, ['V', 'S', 'E', 'S']);
+
+        let PeerSegmentDetail::Key { key_id, .. } = &decoded.segments[2].detail else {
+            panic!("E segment is not a key");
+        };
+        assert_eq!(key_id, &format!("{did}#key-2"));
+
+        let expanded: Vec<&Value> = decoded
+            .segments
+            .iter()
+            .filter_map(|s| match &s.detail {
+                PeerSegmentDetail::Service { expanded, .. } => Some(expanded),
+                PeerSegmentDetail::Key { .. } => None,
+            })
+            .collect();
+        assert_eq!(expanded[0]["id"], format!("{did}#service"));
+        assert_eq!(expanded[1]["id"], format!("{did}#service-1"));
+        assert_eq!(decoded.segments[1].purpose, "service");
+    }
+
+    #[test]
+    fn rejects_other_methods_numalgos_and_purposes() {
+        assert!(
+            decode("did:key:z6Mk")
+                .unwrap_err()
+                .contains("Not a did:peer")
+        );
+        assert!(
+            decode("did:peer:0z6Mk")
+                .unwrap_err()
+                .contains("numalgo '0'")
+        );
+        assert!(decode("did:peer:").unwrap_err().contains("Empty"));
+        let err = decode(&format!("did:peer:2.X{ED25519}")).unwrap_err();
+        assert!(err.contains("Invalid purpose code 'X'"), "{err}");
+        let err = decode("did:peer:2.Snot-base64!").unwrap_err();
+        assert!(err.contains("Service decode error"), "{err}");
+    }
+}
@@ -0,0 +1,228 @@
+/// Structural decoder for packed DIDComm envelopes, for inspector annotations.
+///
+/// Nothing is decrypted or verified. A JWE's base64url `protected` header is
+/// decoded (`alg`, `enc`, `skid`, `apu`/`apv`, `epk`), and its recipient key IDs
+/// are listed with the sizes of `iv`, `ciphertext`, `tag` and each
+/// `encrypted_key`. A JWS's payload and per-signature protected headers are
+/// decoded. Key IDs are mapped to the DID they belong to and, when known, its alias.
+use serde_json::{Map, Value, json};
+
+use crate::flows::attachments::decode_base64;
+
+/// Decode `raw` if it is a JWE or JWS in general JSON serialization.
+/// `alias_for_did` names the DIDs we know.
+pub fn decode(raw: &Value, alias_for_did: impl Fn(&str) -> Option<String>) -> Option<Value> {
+    if raw.get("ciphertext").is_some() {
+        Some(decode_jwe(raw, &alias_for_did))
+    } else if raw.get("signatures").is_some() {
+        Some(decode_jws(raw, &alias_for_did))
+    } else {
+        None
+    }
+}
+
+fn decode_jwe(raw: &Value, alias_for_did: &impl Fn(&str) -> Option<String>) -> Value {
+    let protected = raw
+        .get("protected")
+        .and_then(Value::as_str)
+        .and_then(decode_json)
+        .unwrap_or(Value::Null);
+    let field = |name: &str| protected.get(name).cloned().unwrap_or(Value::Null);
+
+    let recipients: Vec<Value> = raw
+        .get("recipients")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|r| {
+            let kid = r.pointer("/header/kid").and_then(Value::as_str);
+            let mut recipient = key_owner(kid, alias_for_did);
+            recipient["encrypted_key_bytes"] = json!(decoded_len(r.get("encrypted_key")));
+            recipient
+        })
+        .collect();
+    let skid = protected.get("skid").and_then(Value::as_str);
+
+    json!({
+        "format": "jwe",
+        "typ": field("typ"),
+        "alg": field("alg"),
+        "enc": field("enc"),
+        "sender": skid.map(|kid| key_owner(Some(kid), alias_for_did)),
+        // ECDH-1PU binds the sender key ID (apu) and the sorted recipient key IDs (apv)
+        "apu": protected
+            .get("apu")
+            .and_then(Value::as_str)
+            .and_then(decode_base64)
+            .map(|apu| String::from_utf8_lossy(&apu).into_owned()),
+        "apv": protected
+            .get("apv")
+            .and_then(Value::as_str)
+            .and_then(decode_base64)
+            .map(hex),
+        "epk": field("epk"),
+        "recipients": recipients,
+        "iv_bytes": decoded_len(raw.get("iv")),
+        "ciphertext_bytes": decoded_len(raw.get("ciphertext")),
+        "tag_bytes": decoded_len(raw.get("tag")),
+        "protected": protected,
+    })
+}
+
+fn decode_jws(raw: &Value, alias_for_did: &impl Fn(&str) -> Option<String>) -> Value {
+    let payload = raw.get("payload").and_then(Value::as_str);
+    let decoded_payload = payload.and_then(decode_base64);
+
+    let signatures: Vec<Value> = raw
+        .get("signatures")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|s| {
+            let protected = s
+                .get("protected")
+                .and_then(Value::as_str)
+                .and_then(decode_json)
+                .unwrap_or(Value::Null);
+            let kid = s
+                .pointer("/header/kid")
+                .or_else(|| protected.get("kid"))
+                .and_then(Value::as_str);
+            let mut signature = key_owner(kid, alias_for_did);
+            signature["alg"] = protected.get("alg").cloned().unwrap_or(Value::Null);
+            signature["signature_bytes"] = json!(decoded_len(s.get("signature")));
+            signature["protected"] = protected;
+            signature
+        })
+        .collect();
+
+    json!({
+        "format": "jws",
+        "payload_bytes": decoded_payload.as_ref().map(Vec::len),
+        "payload": decoded_payload
+            .and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok()),
+        "signatures": signatures,
+    })
+}
+
+/// `{kid, did, alias}` for a key ID of the form `<did>#<fragment>`.
+fn key_owner(kid: Option<&str>, alias_for_did: &impl Fn(&str) -> Option<String>) -> Value {
+    let did = kid.map(|kid| kid.split('#').next().unwrap_or(kid));
+    let mut owner = Map::new();
+    owner.insert("kid".into(), json!(kid));
+    owner.insert("did".into(), json!(did));
+    owner.insert("alias".into(), json!(did.and_then(alias_for_did)));
+    Value::Object(owner)
+}
+
+fn decode_json(data: &str) -> Option<Value> {
+    serde_json::from_slice(&decode_base64(data)?).ok()
+}
+
+/// Decoded length of a base64url field.
+fn decoded_len(field: Option<&Value>) -> Option<usize> {
+    field
+        .and_then(Value::as_str)
+        .and_then(decode_base64)
+        .map(|bytes| bytes.len())
+}
+
+fn hex(bytes: Vec<u8>) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+
+    use super::*;
+
+    const ALICE: &str = "did:peer:2.alice";
+    const BOB: &str = "did:peer:2.bob";
+
+    fn b64(data: impl AsRef<[u8]>) -> String {
+        URL_SAFE_NO_PAD.encode(data)
+    }
+
+    fn aliases(did: &str) -> Option<String> {
+        match did {
+            ALICE => Some("alice".into()),
+            BOB => Some("bob".into()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn decodes_an_authcrypt_jwe() {
+        let protected = json!({
+            "typ": "application/didcomm-encrypted+json",
+            "alg": "ECDH-1PU+A256KW",
+            "enc": "A256CBC-HS512",
+            "skid": format!("{ALICE}#key-2"),
+            "apu": b64(format!("{ALICE}#key-2")),
+            "apv": b64([0xab, 0x01]),
+        });
+        let jwe = json!({
+            "protected": b64(protected.to_string()),
+            "recipients": [
+                {"header": {"kid": format!("{BOB}#key-2")}, "encrypted_key": b64([0; 40])},
+                {"header": {"kid": "did:example:carol#x"}, "encrypted_key": b64([0; 40])},
+            ],
+            "iv": b64([0; 16]),
+            "ciphertext": b64([0; 100]),
+            "tag": b64([0; 32]),
+        });
+
+        let decoded = decode(&jwe, aliases).unwrap();
+        assert_eq!(decoded["format"], "jwe");
+        assert_eq!(decoded["alg"], "ECDH-1PU+A256KW");
+        assert_eq!(decoded["sender"]["did"], ALICE);
+        assert_eq!(decoded["sender"]["alias"], "alice");
+        assert_eq!(decoded["apu"], format!("{ALICE}#key-2"));
+        assert_eq!(decoded["apv"], "ab01");
+        assert_eq!(decoded["recipients"][0]["alias"], "bob");
+        assert_eq!(decoded["recipients"][0]["encrypted_key_bytes"], 40);
+        assert_eq!(decoded["recipients"][1]["did"], "did:example:carol");
+        assert_eq!(decoded["recipients"][1]["alias"], Value::Null);
+        assert_eq!(decoded["iv_bytes"], 16);
+        assert_eq!(decoded["ciphertext_bytes"], 100);
+        assert_eq!(decoded["tag_bytes"], 32);
+    }
+
+    #[test]
+    fn anoncrypt_jwe_has_no_sender() {
+        let protected = json!({"alg": "ECDH-ES+A256KW", "enc": "A256GCM"});
+        let jwe = json!({"protected": b64(protected.to_string()), "ciphertext": b64("x")});
+        let decoded = decode(&jwe, aliases).unwrap();
+        assert_eq!(decoded["sender"], Value::Null);
+        assert_eq!(decoded["recipients"], json!([]));
+    }
+
+    #[test]
+    fn decodes_a_jws() {
+        let payload =
+            json!({"id": "msg-1", "type": "https://didcomm.org/basicmessage/2.0/message"});
+        let protected = json!({"alg": "EdDSA", "typ": "application/didcomm-signed+json"});
+        let jws = json!({
+            "payload": b64(payload.to_string()),
+            "signatures": [{
+                "protected": b64(protected.to_string()),
+                "header": {"kid": format!("{ALICE}#key-1")},
+                "signature": b64([0; 64]),
+            }],
+        });
+
+        let decoded = decode(&jws, aliases).unwrap();
+        assert_eq!(decoded["format"], "jws");
+        assert_eq!(decoded["payload"], payload);
+        assert_eq!(decoded["payload_bytes"], payload.to_string().len());
+        assert_eq!(decoded["signatures"][0]["alias"], "alice");
+        assert_eq!(decoded["signatures"][0]["alg"], "EdDSA");
+        assert_eq!(decoded["signatures"][0]["signature_bytes"], 64);
+    }
+
+    #[test]
+    fn ignores_plaintext_messages() {
+        assert!(decode(&json!({"id": "msg-1", "body": {}}), aliases).is_none());
+    }
+}
@@ -0,0 +1,139 @@
+/// Typed errors raised by the flows and API handlers.
+///
+/// Each variant maps to an HTTP status and a stable machine-readable `code`
+/// so API clients can branch on the failure without matching message text.
+use std::fmt;
+
+use affinidi_messaging_sdk::errors::ATMError;
+use axum::http::StatusCode;
+
+#[derive(Debug)]
+pub enum FlowError {
+    /// No identity is registered under `alias`; `role` says which side of the flow it was.
+    UnknownAlias { role: &'static str, alias: String },
+    /// The request itself is invalid (empty body, reserved alias, ...).
+    Validation(String),
+    /// The requested record (thread, message, ...) does not exist.
+    NotFound(String),
+    /// An identity with this alias already exists.
+    AliasExists(String),
+    /// A DID could not be resolved.
+    Resolution(String),
+    /// Building, signing or encrypting a DIDComm message failed.
+    Packing(String),
+    /// Wrapping a message in a routing/forward envelope failed.
+    Forwarding(String),
+    /// The mediator could not be reached or returned an error.
+    Transport(String),
+    /// The expected reply did not arrive in time.
+    Timeout(String),
+    /// The mediator's access lists rejected the request.
+    AccessDenied(String),
+    /// The other party answered with a DIDComm problem report.
+    ProblemReport { code: String, comment: String },
+    /// The endpoint needs a mediator, but the server is replaying an archive.
+    Unavailable(String),
+    /// Anything else (profile activation, key generation, ...).
+    Internal(String),
+}
+
+impl FlowError {
+    pub fn unknown_alias(role: &'static str, alias: &str) -> Self {
+        Self::UnknownAlias {
+            role,
+            alias: alias.to_string(),
+        }
+    }
+
+    /// Classify an SDK error raised while talking to the mediator, picking out
+    /// ACL rejections from other transport failures.
+    pub fn from_atm(context: &str, err: ATMError) -> Self {
+        let acl_denied = match &err {
+            ATMError::ACLDenied(_) => true,
+            ATMError::ProblemReport(code, ..) | ATMError::MediatorError(code, _) => {
+                is_acl_code(code)
+            }
+            _ => false,
+        };
+        if acl_denied {
+            Self::AccessDenied(format!("{context}: {err}"))
+        } else {
+            Self::Transport(format!("{context}: {err}"))
+        }
+    }
+
+    /// Map a problem report received in reply to a flow's message, picking
+    /// out ACL rejections.
+    pub fn from_problem_report(code: &str, comment: &str) -> Self {
+        if is_acl_code(code) {
+            Self::AccessDenied(format!("{code}: {comment}"))
+        } else {
+            Self::ProblemReport {
+                code: code.to_string(),
+                comment: comment.to_string(),
+            }
+        }
+    }
+
+    /// Machine-readable error code returned in `ApiError::code`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnknownAlias { .. } => "unknown_alias",
+            Self::Validation(_) => "validation_failed",
+            Self::NotFound(_) => "not_found",
+            Self::AliasExists(_) => "alias_exists",
+            Self::Resolution(_) => "did_resolution_failed",
+            Self::Packing(_) => "packing_failed",
+            Self::Forwarding(_) => "forwarding_failed",
+            Self::Transport(_) => "mediator_transport",
+            Self::Timeout(_) => "timeout",
+            Self::AccessDenied(_) => "acl_denied",
+            Self::ProblemReport { .. } => "problem_report",
+            Self::Unavailable(_) => "replay_mode",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::UnknownAlias { .. } | Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Validation(_) | Self::Resolution(_) => StatusCode::BAD_REQUEST,
+            Self::AliasExists(_) => StatusCode::CONFLICT,
+            Self::Packing(_) | Self::Forwarding(_) | Self::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            Self::Transport(_) | Self::ProblemReport { .. } => StatusCode::BAD_GATEWAY,
+            Self::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Self::AccessDenied(_) => StatusCode::FORBIDDEN,
+            Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+/// Mediator problem-report codes for access-list / authorization rejections
+/// (e.g. `e.p.authorization.access_list.denied`).
+fn is_acl_code(code: &str) -> bool {
+    code.contains("authorization") || code.contains("access_list") || code.contains("acl")
+}
+
+impl fmt::Display for FlowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownAlias { role, alias } => write!(f, "Unknown {role}: {alias}"),
+            Self::AliasExists(alias) => write!(f, "Identity '{alias}' already exists"),
+            Self::ProblemReport { code, comment } => write!(f, "Problem report {code}: {comment}"),
+            Self::Validation(msg)
+            | Self::NotFound(msg)
+            | Self::Resolution(msg)
+            | Self::Packing(msg)
+            | Self::Forwarding(msg)
+            | Self::Transport(msg)
+            | Self::Timeout(msg)
+            | Self::AccessDenied(msg)
+            | Self::Unavailable(msg)
+            | Self::Internal(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for FlowError {}
@@ -0,0 +1,151 @@
+/// DIDComm v2 attachments for outgoing messages — inline base64, inline JSON
+/// or external links with a content hash.
+///
+/// Request attachments use the DIDComm descriptor shape (`id`, `media_type`,
+/// `filename`, `description`, `data`), are validated here and converted into
+/// `Attachment`s for the `Message` builder.
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use affinidi_messaging_didcomm::{Attachment, AttachmentData};
+
+use crate::error::FlowError;
+
+/// An attachment as supplied in a send request.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AttachmentSpec {
+    pub id: Option<String>,
+    pub media_type: Option<String>,
+    pub filename: Option<String>,
+    pub description: Option<String>,
+    /// Size of linked content (computed for inline data).
+    pub byte_count: Option<u64>,
+    /// `{"base64": ...}`, `{"json": ...}` or `{"links": [...], "hash": ...}`.
+    pub data: AttachmentData,
+}
+
+impl AttachmentSpec {
+    /// Validate the descriptor and build the DIDComm attachment. Inline base64
+    /// is re-encoded as unpadded base64url and gets its decoded size as
+    /// `byte_count`; unnamed attachments get `id` `attachment-<n>`.
+    pub fn build(&self, index: usize) -> Result<Attachment, FlowError> {
+        let name = self
+            .id
+            .clone()
+            .unwrap_or_else(|| format!("attachment-{}", index + 1));
+        let (mut builder, byte_count) = match &self.data {
+            AttachmentData::Base64 { value } => {
+                let decoded = decode_base64(&value.base64).ok_or_else(|| {
+                    FlowError::Validation(format!("attachment {name}: data.base64 is not base64"))
+                })?;
+                (
+                    Attachment::base64(URL_SAFE_NO_PAD.encode(&decoded)),
+                    Some(decoded.len() as u64),
+                )
+            }
+            AttachmentData::Json { value } => {
+                let size = serde_json::to_vec(&value.json).map_or(0, |v| v.len());
+                (Attachment::json(value.json.clone()), Some(size as u64))
+            }
+            AttachmentData::Links { value } => {
+                if value.links.is_empty() || value.hash.is_empty() {
+                    return Err(FlowError::Validation(format!(
+                        "attachment {name}: links need at least one URL and a hash"
+                    )));
+                }
+                (
+                    Attachment::links(value.links.clone(), value.hash.clone()),
+                    self.byte_count,
+                )
+            }
+        };
+
+        builder = builder.id(name);
+        if let Some(media_type) = &self.media_type {
+            builder = builder.media_type(media_type.clone());
+        }
+        if let Some(filename) = &self.filename {
+            builder = builder.filename(filename.clone());
+        }
+        if let Some(description) = &self.description {
+            builder = builder.description(description.clone());
+        }
+        if let Some(byte_count) = byte_count {
+            builder = builder.byte_count(byte_count);
+        }
+        Ok(builder.finalize())
+    }
+}
+
+/// DIDComm uses base64url, but accept standard base64 (with or without padding) too.
+pub fn decode_base64(data: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(data.trim_end_matches('='))
+        .or_else(|_| STANDARD.decode(data))
+        .ok()
+}
+
+/// Per-attachment summary for packet annotations, plus the total number of
+/// bytes the attachments add to the plaintext.
+pub fn summarize(attachments: &[Attachment]) -> (Value, usize) {
+    let mut total = 0;
+    let summary = attachments
+        .iter()
+        .map(|a| {
+            let (kind, encoded) = match &a.data {
+                AttachmentData::Base64 { value } => ("base64", value.base64.len()),
+                AttachmentData::Json { value } => (
+                    "json",
+                    serde_json::to_vec(&value.json).map_or(0, |v| v.len()),
+                ),
+                AttachmentData::Links { value } => (
+                    "links",
+                    serde_json::to_vec(&value.links).map_or(0, |v| v.len()) + value.hash.len(),
+                ),
+            };
+            total += encoded;
+            json!({
+                "id": a.id,
+                "kind": kind,
+                "media_type": a.media_type,
+                "filename": a.filename,
+                "byte_count": a.byte_count,
+                "encoded_bytes": encoded,
+            })
+        })
+        .collect();
+    (Value::Array(summary), total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(data: Value) -> AttachmentSpec {
+        serde_json::from_value(json!({ "data": data })).unwrap()
+    }
+
+    #[test]
+    fn base64_is_reencoded_as_unpadded_base64url() {
+        // Standard alphabet with padding: "\xfb\xff" -> "+/8="
+        let attachment = spec(json!({ "base64": "+/8=" })).build(0).unwrap();
+        let AttachmentData::Base64 { value } = &attachment.data else {
+            panic!("expected inline base64");
+        };
+        assert_eq!(value.base64, "-_8");
+        assert_eq!(attachment.byte_count, Some(2));
+        assert_eq!(attachment.id.as_deref(), Some("attachment-1"));
+    }
+
+    #[test]
+    fn invalid_base64_is_rejected() {
+        let err = spec(json!({ "base64": "not base64!" }))
+            .build(0)
+            .unwrap_err();
+        assert!(matches!(err, FlowError::Validation(_)));
+    }
+}
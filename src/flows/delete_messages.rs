@@ -0,0 +1,132 @@
+/// Message deletion — removes queued messages from an identity's mediator
+/// inbox through the ATM delete-messages API.
+///
+/// Each batch sent to the mediator is emitted as a request/response pair of
+/// `MessageDelete` events so the inspector shows exactly what was removed.
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+use uuid::Uuid;
+
+use affinidi_messaging_sdk::messages::{DeleteMessageRequest, Folder};
+
+use crate::error::FlowError;
+use crate::mediator::AppState;
+use crate::packet_logger::{PacketDirection, PacketEvent, PacketStep};
+
+/// The mediator rejects delete requests with more IDs than this.
+const MAX_IDS_PER_REQUEST: usize = 100;
+
+/// Which messages to delete: an explicit ID list or the keyword `"all"`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum DeleteTarget {
+    Ids(Vec<String>),
+    Keyword(String),
+}
+
+/// Result of deleting a single message ID.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeleteOutcome {
+    pub msg_id: String,
+    pub deleted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Delete `target` from `alias`'s mediator inbox and report per-ID results.
+pub async fn delete_messages(
+    state: &Arc<AppState>,
+    alias: &str,
+    target: &DeleteTarget,
+) -> Result<(Vec<PacketEvent>, Vec<DeleteOutcome>), FlowError> {
+    let identity = state
+        .identity(alias)
+        .await
+        .ok_or_else(|| FlowError::unknown_alias("alias", alias))?;
+    let atm = &*state.atm;
+    let did = identity.did().to_string();
+    let mediator_did = identity.mediator_did.clone();
+    let alias = identity.alias().to_lowercase();
+
+    let ids = match target {
+        DeleteTarget::Ids(ids) if ids.is_empty() => {
+            return Err(FlowError::Validation("message_ids must not be empty".into()));
+        }
+        DeleteTarget::Ids(ids) => ids.clone(),
+        DeleteTarget::Keyword(k) if k.eq_ignore_ascii_case("all") => atm
+            .list_messages(&identity.profile, Folder::Inbox)
+            .await
+            .map_err(|e| FlowError::from_atm("list_messages failed", e))?
+            .into_iter()
+            .map(|m| m.msg_id)
+            .collect(),
+        DeleteTarget::Keyword(k) => {
+            return Err(FlowError::Validation(format!(
+                "message_ids must be a list of IDs or \"all\", got \"{k}\""
+            )));
+        }
+    };
+
+    let correlation_id = Uuid::new_v4().to_string();
+    let mut events = Vec::new();
+    let mut outcomes = Vec::with_capacity(ids.len());
+
+    for batch in ids.chunks(MAX_IDS_PER_REQUEST) {
+        let request = DeleteMessageRequest {
+            message_ids: batch.to_vec(),
+        };
+        let request_evt = PacketEvent::new(
+            PacketDirection::Outbound,
+            &did,
+            &mediator_did,
+            PacketStep::MessageDelete,
+            json!(request),
+            Some(correlation_id.clone()),
+        )
+        .with_aliases(&alias, "mediator");
+        let _ = state.packet_tx.send(request_evt.clone());
+        events.push(request_evt);
+
+        let response = atm
+            .delete_messages_direct(&identity.profile, &request)
+            .await
+            .map_err(|e| FlowError::from_atm("delete_messages failed", e))?;
+
+        let response_evt = PacketEvent::new(
+            PacketDirection::Inbound,
+            &mediator_did,
+            &did,
+            PacketStep::MessageDelete,
+            json!(response),
+            Some(correlation_id.clone()),
+        )
+        .with_aliases("mediator", &alias);
+        let _ = state.packet_tx.send(response_evt.clone());
+        events.push(response_evt);
+
+        // IDs the mediator neither confirmed nor rejected are reported as not deleted
+        for id in batch {
+            let error = response
+                .errors
+                .iter()
+                .find(|(err_id, _)| err_id == id)
+                .map(|(_, reason)| reason.clone());
+            let deleted = error.is_none() && response.success.contains(id);
+            outcomes.push(DeleteOutcome {
+                msg_id: id.clone(),
+                deleted,
+                error: error.or_else(|| (!deleted).then(|| "not confirmed by mediator".into())),
+            });
+        }
+    }
+
+    info!(
+        "{alias} deleted {}/{} message(s)",
+        outcomes.iter().filter(|o| o.deleted).count(),
+        outcomes.len()
+    );
+    Ok((events, outcomes))
+}
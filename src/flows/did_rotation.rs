@@ -0,0 +1,124 @@
+/// DID rotation flow — moves an identity to a fresh did:peer and proves the
+/// move with a `from_prior` JWT signed by the old DID's key.
+///
+/// The JWT rides in the `from_prior` header of the identity's subsequent
+/// messages; recipients validate it on unpack and move their contact record
+/// from the old DID to the new one (see `inbound`).
+use std::sync::Arc;
+
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+use affinidi_messaging_didcomm::{FromPrior, UnpackMetadata};
+
+use crate::error::FlowError;
+use crate::flows::send_message::{self, SendOptions};
+use crate::flows::unix_now;
+use crate::identity::{Identity, generate_peer_profile};
+use crate::mediator::AppState;
+use crate::packet_logger::{PacketDirection, PacketEvent, PacketStep};
+
+/// Rotate `alias` to a new did:peer. If `notify` names a peer, a basic message
+/// carrying the `from_prior` header is sent to it straight away.
+///
+/// Returns the new identity alongside the emitted events.
+pub async fn rotate_did(
+    state: &Arc<AppState>,
+    alias: &str,
+    notify: Option<&str>,
+) -> Result<(Vec<PacketEvent>, Identity), FlowError> {
+    let old = state
+        .identity(alias)
+        .await
+        .ok_or_else(|| FlowError::unknown_alias("alias", alias))?;
+    let correlation_id = Uuid::new_v4().to_string();
+    let mut events = Vec::new();
+
+    // ── Step 1: Generate the new did:peer ───────────────────────────────
+    let tdk_profile = generate_peer_profile(old.alias(), &old.mediator_did)
+        .map_err(|e| FlowError::Internal(format!("did:peer generation failed: {e}")))?;
+
+    // ── Step 2: Sign from_prior with the old DID's authentication key ────
+    let now = unix_now();
+    let claims = FromPrior::build(old.did().to_string(), tdk_profile.did.clone())
+        .iat(now)
+        .jti(Uuid::new_v4().to_string())
+        .finalize();
+    let tdk = state.tdk.get_shared_state();
+    let (jwt, issuer_kid) = claims
+        .pack(None, &tdk.did_resolver, &tdk.secrets_resolver)
+        .await
+        .map_err(|e| FlowError::Packing(format!("from_prior signing failed: {e}")))?;
+
+    // ── Step 3: Swap the profile, ACLs and listener over to the new DID ──
+    let new = state.rotate_identity(&old, &tdk_profile, jwt.clone()).await?;
+
+    let evt = PacketEvent::new(
+        PacketDirection::Outbound,
+        old.did(),
+        new.did(),
+        PacketStep::DidRotation,
+        json!({
+            "from_prior": &jwt,
+            "claims": &claims,
+            "issuer_kid": &issuer_kid,
+        }),
+        Some(correlation_id),
+    )
+    .with_aliases(&old.alias().to_lowercase(), &new.alias().to_lowercase())
+    .with_annotations(json!({
+        "previous_did": old.did(),
+        "new_did": new.did(),
+        "detail": "JWT signed with the old DID's key; sent as the `from_prior` header of subsequent messages.",
+    }));
+    let _ = state.packet_tx.send(evt.clone());
+    events.push(evt);
+
+    // ── Step 4: Announce the rotation with the next message ─────────────
+    if let Some(peer) = notify {
+        events.extend(
+            send_message::send_message(
+                state,
+                new.alias(),
+                peer,
+                "DID rotated",
+                &SendOptions::default(),
+            )
+            .await?,
+        );
+    }
+
+    Ok((events, new))
+}
+
+/// Check a `from_prior` header already verified by unpack against the
+/// message it arrived on. Returns the annotations for the inspector and
+/// whether the rotation is acceptable.
+pub fn validate(
+    from: Option<&str>,
+    metadata: &UnpackMetadata,
+) -> Option<(FromPrior, Value, bool)> {
+    let claims = metadata.from_prior.clone()?;
+    let now = unix_now();
+    let issuer_kid = metadata.from_prior_issuer_kid.as_deref().unwrap_or_default();
+
+    let problem = if from != Some(claims.sub.as_str()) {
+        Some("`from` does not match the from_prior subject")
+    } else if issuer_kid.split('#').next() != Some(claims.iss.as_str()) {
+        Some("from_prior was not signed by its issuer")
+    } else if claims.exp.is_some_and(|exp| exp <= now) {
+        Some("from_prior has expired")
+    } else if claims.nbf.is_some_and(|nbf| nbf > now) {
+        Some("from_prior is not yet valid")
+    } else {
+        None
+    };
+    let annotations = json!({
+        "previous_did": &claims.iss,
+        "new_did": &claims.sub,
+        "issuer_kid": issuer_kid,
+        "valid": problem.is_none(),
+        "detail": problem.unwrap_or("Signature verified against the previous DID's key"),
+    });
+    Some((claims, annotations, problem.is_none()))
+}
@@ -0,0 +1,205 @@
+/// Discover Features 2.0 flow — asks a peer or the mediator which protocols
+/// (or goal codes / headers) it supports and waits for the disclosure.
+///
+/// Like the trust ping, the reply is picked up by the sender's inbound
+/// listener and handed back to this flow by thread ID.
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use affinidi_messaging_sdk::protocols::discover_features::{
+    DiscoverFeaturesQuery, FeatureType, Query,
+};
+
+use crate::error::FlowError;
+use crate::flows::problem_report::{PROBLEM_REPORT_TYPE, ProblemReportInfo};
+use crate::identity::IdentityRegistry;
+use crate::mediator::AppState;
+use crate::packet_logger::{PacketDirection, PacketEvent, PacketStep};
+use crate::store::MessageDirection;
+
+pub const QUERIES_TYPE: &str = "https://didcomm.org/discover-features/2.0/queries";
+
+/// Protocols this demo's identities disclose when queried by a peer.
+pub const DISCLOSED_PROTOCOLS: [&str; 3] = [
+    "https://didcomm.org/trust-ping/2.0",
+    "https://didcomm.org/basicmessage/2.0",
+    "https://didcomm.org/discover-features/2.0",
+];
+
+/// How long to wait for the disclosure after the query was accepted by the mediator.
+const DISCLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Kind of feature a query asks about.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeatureKind {
+    #[default]
+    Protocol,
+    GoalCode,
+    Header,
+}
+
+/// A single feature query — `match` may end in `*` for prefix matching.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FeatureQuery {
+    #[serde(default)]
+    pub feature_type: FeatureKind,
+    #[serde(rename = "match")]
+    pub match_: String,
+}
+
+impl From<&FeatureQuery> for Query {
+    fn from(q: &FeatureQuery) -> Self {
+        Query {
+            feature_type: match q.feature_type {
+                FeatureKind::Protocol => FeatureType::Protocol,
+                FeatureKind::GoalCode => FeatureType::GoalCode,
+                FeatureKind::Header => FeatureType::Header,
+            },
+            match_: q.match_.clone(),
+        }
+    }
+}
+
+/// Send a discover-features query from `from_alias` to `to_alias` ("mediator"
+/// targets the sender's own mediator) and return the disclosed features.
+pub async fn discover_features(
+    state: &Arc<AppState>,
+    from_alias: &str,
+    to_alias: &str,
+    queries: &[FeatureQuery],
+) -> Result<(Vec<PacketEvent>, Value), FlowError> {
+    if queries.is_empty() {
+        return Err(FlowError::Validation("at least one query is required".into()));
+    }
+
+    let correlation_id = Uuid::new_v4().to_string();
+    let mut events: Vec<PacketEvent> = Vec::new();
+    let atm = &*state.atm;
+
+    let (sender, target_did) = {
+        let registry = state.identities.read().await;
+        let sender = registry
+            .get(from_alias)
+            .cloned()
+            .ok_or_else(|| FlowError::unknown_alias("sender", from_alias))?;
+        let target_did = if to_alias.eq_ignore_ascii_case("mediator") {
+            sender.mediator_did.clone()
+        } else {
+            registry
+                .get(to_alias)
+                .map(|t| t.did().to_string())
+                .ok_or_else(|| FlowError::unknown_alias("query target", to_alias))?
+        };
+        (sender, target_did)
+    };
+    let sender_did = sender.did().to_string();
+
+    // ── Step 1: Send Query ─────────────────────────────────────────────
+    let query = atm
+        .discover_features()
+        .generate_query_message(
+            &sender_did,
+            &target_did,
+            DiscoverFeaturesQuery {
+                queries: queries.iter().map(Query::from).collect(),
+            },
+        )
+        .map_err(|e| FlowError::Packing(format!("generate_query_message failed: {e}")))?;
+    let query_id = query.id.clone();
+    state.inbound.track(&query_id, &correlation_id);
+
+    // Register for the disclosure before sending so a fast reply can't be missed
+    let disclose_rx = state.inbound.expect_reply(&query_id);
+
+    let query_evt = PacketEvent::new(
+        PacketDirection::Outbound,
+        &sender_did,
+        &target_did,
+        PacketStep::FeaturesQuery,
+        serde_json::to_value(&query).unwrap_or_else(|_| json!({"id": &query_id})),
+        Some(correlation_id.clone()),
+    );
+    let _ = state.packet_tx.send(query_evt.clone());
+    events.push(query_evt);
+
+    let send_result = async {
+        let (packed, _) = atm
+            .pack_encrypted(&query, &target_did, Some(&sender_did), Some(&sender_did), None)
+            .await
+            .map_err(|e| FlowError::Packing(format!("pack_encrypted failed: {e}")))?;
+        atm.send_message(&sender.profile, &packed, &query_id, false, true)
+            .await
+            .map_err(|e| FlowError::from_atm("send_query failed", e))?;
+        Ok::<_, FlowError>(())
+    }
+    .await;
+    if let Err(e) = send_result {
+        state.inbound.cancel_reply(&query_id);
+        return Err(e);
+    }
+    info!("{from_alias} → {to_alias} discover-features query sent ({query_id})");
+    state.persist_message(
+        MessageDirection::Sent,
+        &query,
+        Some(&IdentityRegistry::key(from_alias)),
+        Some(&IdentityRegistry::key(to_alias)),
+    );
+
+    // ── Step 2: Receive Disclose via the sender's inbound listener ──────
+    match tokio::time::timeout(DISCLOSE_TIMEOUT, disclose_rx).await {
+        // Already emitted as a ProblemReport event by the inbound listener
+        Ok(Ok(inbound)) if inbound.message.type_ == PROBLEM_REPORT_TYPE => {
+            let (code, comment) = ProblemReportInfo::parse(&inbound.message)
+                .map(|r| (r.code, r.message))
+                .unwrap_or_else(|| ("unknown".into(), "malformed problem report".into()));
+            Err(FlowError::from_problem_report(&code, &comment))
+        }
+        Ok(Ok(inbound)) => {
+            let disclosures = inbound
+                .message
+                .body
+                .get("disclosures")
+                .cloned()
+                .unwrap_or_else(|| json!([]));
+            let disclose_evt = PacketEvent::new(
+                PacketDirection::Inbound,
+                &target_did,
+                &sender_did,
+                PacketStep::FeaturesDisclose,
+                json!({
+                    "message": serde_json::to_value(&inbound.message)
+                        .unwrap_or_else(|_| json!({"id": inbound.message.id})),
+                    "unpack_metadata": &*inbound.metadata,
+                }),
+                Some(correlation_id.clone()),
+            );
+            info!("{from_alias} ← {to_alias} disclosure received");
+            let _ = state.packet_tx.send(disclose_evt.clone());
+            events.push(disclose_evt);
+            Ok((events, disclosures))
+        }
+        _ => {
+            state.inbound.cancel_reply(&query_id);
+            debug!("No disclosure received within {DISCLOSE_TIMEOUT:?}");
+            let timeout_evt = PacketEvent::new(
+                PacketDirection::Inbound,
+                &target_did,
+                &sender_did,
+                PacketStep::FeaturesDisclose,
+                json!({ "status": "timeout", "detail": "Disclosure not received within timeout" }),
+                Some(correlation_id.clone()),
+            );
+            let _ = state.packet_tx.send(timeout_evt);
+            Err(FlowError::Timeout(format!(
+                "No disclosure from {to_alias} within {}s",
+                DISCLOSE_TIMEOUT.as_secs()
+            )))
+        }
+    }
+}
@@ -0,0 +1,429 @@
+/// Chunked file transfer built on DIDComm attachments.
+///
+/// The sender announces the file with a `manifest` message (name, size,
+/// SHA-256 digest, chunk count). It then sends each slice of the file as a
+/// separate authcrypted `chunk` message. Each chunk carries one base64
+/// attachment and is threaded under the manifest. The recipient's inbound
+/// listener collects the chunks in the `TransferStore`, reassembles the file
+/// and verifies the digest. Both sides report progress as `file_transfer`
+/// packet events.
+///
+/// Mediators cap the size of a stored message, so every chunk event records its
+/// envelope size. If the mediator rejects a chunk, the transfer fails and the
+/// error includes that chunk's envelope size.
+use std::sync::Arc;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha256::digest;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use affinidi_messaging_didcomm::{Attachment, AttachmentData, Message};
+
+use crate::error::FlowError;
+use crate::flows::{attachments, unix_now};
+use crate::identity::Identity;
+use crate::mediator::AppState;
+use crate::packet_logger::{PacketDirection, PacketEvent, PacketStep};
+use crate::store::MessageDirection;
+use crate::transfers::{Manifest, Transfer, TransferStatus};
+
+pub const MANIFEST_TYPE: &str = "https://didcomm.org/file-transfer/1.0/manifest";
+pub const CHUNK_TYPE: &str = "https://didcomm.org/file-transfer/1.0/chunk";
+
+/// Chunk size used when the request doesn't set one.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+pub const MIN_CHUNK_SIZE: usize = 1024;
+pub const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Largest file accepted for upload.
+pub const MAX_FILE_BYTES: usize = 32 * 1024 * 1024;
+
+/// A file to send.
+#[derive(Debug)]
+pub struct FileUpload {
+    pub filename: String,
+    pub media_type: String,
+    pub data: Vec<u8>,
+    pub chunk_size: Option<usize>,
+}
+
+/// Outcome of a completed send.
+#[derive(Debug, Serialize)]
+pub struct SendReport {
+    pub transfer: Transfer,
+    /// Bytes handed to the mediator across the manifest and all chunks.
+    pub envelope_bytes: usize,
+    /// Largest single envelope — the figure to compare with the mediator's limit.
+    pub max_envelope_bytes: usize,
+}
+
+/// Body of a chunk message; the bytes travel in its attachment.
+#[derive(Debug, Serialize, Deserialize)]
+struct ChunkBody {
+    transfer_id: String,
+    index: u32,
+}
+
+/// Send `upload` from `from_alias` to `to_alias`, one message per chunk.
+pub async fn send_file(
+    state: &Arc<AppState>,
+    from_alias: &str,
+    to_alias: &str,
+    upload: &FileUpload,
+) -> Result<(Vec<PacketEvent>, SendReport), FlowError> {
+    let chunk_size = upload.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE);
+    if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+        return Err(FlowError::Validation(format!(
+            "chunk_size must be between {MIN_CHUNK_SIZE} and {MAX_CHUNK_SIZE} bytes"
+        )));
+    }
+    if upload.data.is_empty() {
+        return Err(FlowError::Validation("file is empty".into()));
+    }
+    if upload.data.len() > MAX_FILE_BYTES {
+        return Err(FlowError::Validation(format!(
+            "file exceeds {MAX_FILE_BYTES} bytes"
+        )));
+    }
+
+    let sender = state
+        .identity(from_alias)
+        .await
+        .ok_or_else(|| FlowError::unknown_alias("sender", from_alias))?;
+    let recipient = state
+        .identity(to_alias)
+        .await
+        .ok_or_else(|| FlowError::unknown_alias("recipient", to_alias))?;
+    let from_label = sender.alias().to_lowercase();
+    let to_label = recipient.alias().to_lowercase();
+    let correlation_id = Uuid::new_v4().to_string();
+    let mut events = Vec::new();
+
+    // ── Step 1: Announce the file with a manifest ───────────────────────
+    let chunks: Vec<&[u8]> = upload.data.chunks(chunk_size).collect();
+    let manifest = Manifest {
+        transfer_id: Uuid::new_v4().to_string(),
+        filename: upload.filename.clone(),
+        media_type: upload.media_type.clone(),
+        size: upload.data.len() as u64,
+        sha256: digest(upload.data.as_slice()),
+        chunk_size,
+        chunk_count: chunks.len() as u32,
+    };
+    let transfer_id = manifest.transfer_id.clone();
+    let manifest_msg = Message::build(
+        Uuid::new_v4().into(),
+        MANIFEST_TYPE.into(),
+        serde_json::to_value(&manifest).unwrap_or_default(),
+    )
+    .thid(transfer_id.clone())
+    .from(sender.did().to_string())
+    .to(recipient.did().to_string())
+    .created_time(unix_now())
+    .finalize();
+    state.inbound.track(&transfer_id, &correlation_id);
+    state
+        .transfers
+        .start_sending(sender.alias(), recipient.did(), &manifest);
+
+    let packed = pack(state, &sender, recipient.did(), &manifest_msg)
+        .await
+        .inspect_err(|e| fail(state, &sender, &transfer_id, e))?;
+    deliver(state, &sender, &packed, &manifest_msg.id)
+        .await
+        .inspect_err(|e| fail(state, &sender, &transfer_id, e))?;
+    state.persist_message(
+        MessageDirection::Sent,
+        &manifest_msg,
+        Some(&from_label),
+        Some(&to_label),
+    );
+    let mut envelope_bytes = packed.len();
+    let mut max_envelope_bytes = envelope_bytes;
+    let evt = PacketEvent::new(
+        PacketDirection::Outbound,
+        sender.did(),
+        recipient.did(),
+        PacketStep::FileTransfer,
+        serde_json::to_value(&manifest_msg).unwrap_or_else(|_| json!({"id": manifest_msg.id})),
+        Some(correlation_id.clone()),
+    )
+    .with_aliases(&from_label, &to_label)
+    .with_annotations(json!({
+        "stage": "manifest",
+        "envelope_bytes": envelope_bytes,
+        "detail": "Announces the file; the chunks follow in the same thread.",
+    }));
+    let _ = state.packet_tx.send(evt.clone());
+    events.push(evt);
+
+    // ── Step 2: One authcrypted message per chunk ────────────────────────
+    let mut bytes_sent: u64 = 0;
+    for (index, chunk) in chunks.iter().enumerate() {
+        let index = index as u32;
+        let attachment = Attachment::base64(URL_SAFE_NO_PAD.encode(chunk))
+            .id(format!("chunk-{index}"))
+            .media_type("application/octet-stream".into())
+            .byte_count(chunk.len() as u64)
+            .finalize();
+        let msg = Message::build(
+            Uuid::new_v4().into(),
+            CHUNK_TYPE.into(),
+            json!(ChunkBody {
+                transfer_id: transfer_id.clone(),
+                index,
+            }),
+        )
+        .thid(transfer_id.clone())
+        .from(sender.did().to_string())
+        .to(recipient.did().to_string())
+        .created_time(unix_now())
+        .attachment(attachment)
+        .finalize();
+
+        let packed = pack(state, &sender, recipient.did(), &msg)
+            .await
+            .inspect_err(|e| fail(state, &sender, &transfer_id, e))?;
+        let size = packed.len();
+        deliver(state, &sender, &packed, &msg.id)
+            .await
+            .map_err(|e| {
+                // Most likely the mediator's message size limit — say how big it was
+                let err = match e {
+                    FlowError::Transport(e) => FlowError::Transport(format!(
+                        "chunk {}/{} ({size} byte envelope) rejected: {e}",
+                        index + 1,
+                        manifest.chunk_count,
+                    )),
+                    e => e,
+                };
+                fail(state, &sender, &transfer_id, &err);
+                err
+            })?;
+        state.persist_message(
+            MessageDirection::Sent,
+            &msg,
+            Some(&from_label),
+            Some(&to_label),
+        );
+        envelope_bytes += size;
+        max_envelope_bytes = max_envelope_bytes.max(size);
+        bytes_sent += chunk.len() as u64;
+        state
+            .transfers
+            .chunk_sent(sender.alias(), &transfer_id, chunk.len());
+
+        let evt = PacketEvent::new(
+            PacketDirection::Outbound,
+            sender.did(),
+            recipient.did(),
+            PacketStep::FileTransfer,
+            json!({
+                "msg_id": &msg.id,
+                "transfer_id": &transfer_id,
+                "index": index,
+                "chunk_bytes": chunk.len(),
+                "envelope_bytes": size,
+            }),
+            Some(correlation_id.clone()),
+        )
+        .with_aliases(&from_label, &to_label)
+        .with_annotations(progress(
+            "chunk_sent",
+            index + 1,
+            manifest.chunk_count,
+            bytes_sent,
+            &manifest,
+        ));
+        let _ = state.packet_tx.send(evt.clone());
+        events.push(evt);
+    }
+
+    let transfer = state
+        .transfers
+        .finish(sender.alias(), &transfer_id, None)
+        .ok_or_else(|| FlowError::Internal("transfer record was evicted".into()))?;
+    info!(
+        "{from_alias} → {to_alias}: sent {} ({} bytes) in {} chunks, largest envelope {max_envelope_bytes} bytes",
+        manifest.filename, manifest.size, manifest.chunk_count
+    );
+    Ok((
+        events,
+        SendReport {
+            transfer,
+            envelope_bytes,
+            max_envelope_bytes,
+        },
+    ))
+}
+
+/// Handle a manifest or chunk received by `identity` from `sender_alias` and
+/// report progress.
+pub fn receive(
+    state: &Arc<AppState>,
+    identity: &Identity,
+    msg: &Message,
+    sender_alias: &str,
+    correlation_id: Option<&str>,
+) {
+    let owner = identity.alias();
+    let peer = msg.from.as_deref();
+    let (stage, transfer, raw) = if msg.type_ == MANIFEST_TYPE {
+        let manifest: Manifest = match serde_json::from_value(msg.body.clone()) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                warn!("{owner} received a malformed file manifest {}: {e}", msg.id);
+                return;
+            }
+        };
+        let raw = serde_json::to_value(msg).unwrap_or_else(|_| json!({"id": msg.id}));
+        (
+            "manifest",
+            state.transfers.manifest_received(owner, peer, manifest),
+            raw,
+        )
+    } else {
+        let (body, data) = match parse_chunk(msg) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                warn!("{owner} received a malformed file chunk {}: {e}", msg.id);
+                return;
+            }
+        };
+        let raw = json!({
+            "msg_id": &msg.id,
+            "transfer_id": &body.transfer_id,
+            "index": body.index,
+            "chunk_bytes": data.len(),
+        });
+        let transfer =
+            state
+                .transfers
+                .chunk_received(owner, peer, &body.transfer_id, body.index, data);
+        ("chunk_received", transfer, raw)
+    };
+
+    let annotations = match (&transfer.manifest, transfer.status) {
+        (Some(manifest), TransferStatus::InProgress) => progress(
+            stage,
+            transfer.chunks_done,
+            manifest.chunk_count,
+            transfer.bytes_done,
+            manifest,
+        ),
+        (None, _) => json!({
+            "stage": stage,
+            "chunks_done": transfer.chunks_done,
+            "detail": "Manifest not received yet — buffering chunks.",
+        }),
+        (Some(manifest), TransferStatus::Complete) => {
+            info!(
+                "{owner} reassembled {} ({} bytes)",
+                manifest.filename, manifest.size
+            );
+            json!({
+                "stage": "complete",
+                "filename": &manifest.filename,
+                "size": manifest.size,
+                "sha256": &manifest.sha256,
+                "verified": true,
+                "detail": "All chunks received; SHA-256 digest matches the manifest.",
+            })
+        }
+        (Some(_), TransferStatus::Failed) => {
+            warn!(
+                "{owner} file transfer {} failed: {:?}",
+                transfer.transfer_id, transfer.error
+            );
+            json!({
+                "stage": "failed",
+                "verified": false,
+                "detail": &transfer.error,
+            })
+        }
+    };
+
+    let evt = PacketEvent::new(
+        PacketDirection::Inbound,
+        peer.unwrap_or("anonymous"),
+        identity.did(),
+        PacketStep::FileTransfer,
+        raw,
+        correlation_id.map(str::to_string),
+    )
+    .with_aliases(sender_alias, &owner.to_lowercase())
+    .with_annotations(annotations);
+    let _ = state.packet_tx.send(evt);
+}
+
+/// Authcrypt `msg` from `sender` to `to`, wrapped for the recipient's mediator.
+async fn pack(
+    state: &AppState,
+    sender: &Identity,
+    to: &str,
+    msg: &Message,
+) -> Result<String, FlowError> {
+    let did = sender.did();
+    let (packed, _) = state
+        .atm
+        .pack_encrypted(msg, to, Some(did), Some(did), None)
+        .await
+        .map_err(|e| FlowError::Packing(format!("pack_encrypted failed: {e}")))?;
+    Ok(packed)
+}
+
+/// Hand a packed message to the sender's mediator.
+async fn deliver(
+    state: &AppState,
+    sender: &Identity,
+    packed: &str,
+    msg_id: &str,
+) -> Result<(), FlowError> {
+    state
+        .atm
+        .send_message(&sender.profile, packed, msg_id, false, false)
+        .await
+        .map_err(|e| FlowError::from_atm("send_message failed", e))?;
+    Ok(())
+}
+
+fn fail(state: &AppState, sender: &Identity, transfer_id: &str, err: &FlowError) {
+    warn!(
+        "{} file transfer {transfer_id} failed: {err}",
+        sender.alias()
+    );
+    state
+        .transfers
+        .finish(sender.alias(), transfer_id, Some(err.to_string()));
+}
+
+fn parse_chunk(msg: &Message) -> Result<(ChunkBody, Vec<u8>), String> {
+    let body: ChunkBody = serde_json::from_value(msg.body.clone()).map_err(|e| e.to_string())?;
+    let data = match msg.attachments.as_deref() {
+        Some([attachment]) => match &attachment.data {
+            AttachmentData::Base64 { value } => attachments::decode_base64(&value.base64)
+                .ok_or_else(|| "attachment is not base64".to_string())?,
+            _ => return Err("chunk attachment must be inline base64".into()),
+        },
+        _ => return Err("chunk must carry exactly one attachment".into()),
+    };
+    if data.len() > MAX_CHUNK_SIZE {
+        return Err(format!("chunk exceeds {MAX_CHUNK_SIZE} bytes"));
+    }
+    Ok((body, data))
+}
+
+fn progress(stage: &str, done: u32, total: u32, bytes: u64, manifest: &Manifest) -> Value {
+    json!({
+        "stage": stage,
+        "filename": &manifest.filename,
+        "chunks_done": done,
+        "chunk_count": total,
+        "bytes_done": bytes,
+        "size": manifest.size,
+        "percent": (done * 100).checked_div(total).unwrap_or(100),
+    })
+}
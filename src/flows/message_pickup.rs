@@ -0,0 +1,299 @@
+/// Message Pickup 3.0 flow — drains an identity's mediator queue over DIDComm
+/// rather than the REST fetch shortcut.
+///
+/// status-request → status, delivery-request → delivery, then messages-received
+/// to acknowledge (and delete) what was delivered. Every request goes out with
+/// `return_route: all`, so the mediator answers on the same WebSocket and the
+/// SDK hands the reply straight back to this flow.
+use std::sync::Arc;
+
+use serde_json::{Value, json};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use affinidi_messaging_didcomm::{AttachmentData, Message};
+use affinidi_messaging_sdk::transports::SendMessageResponse;
+
+use crate::error::FlowError;
+use crate::flows::attachments::decode_base64;
+use crate::flows::{problem_report, unix_now};
+use crate::identity::Identity;
+use crate::mediator::AppState;
+use crate::packet_logger::{PacketDirection, PacketEvent, PacketStep};
+use crate::store::MessageDirection;
+
+const PICKUP_PROTOCOL: &str = "https://didcomm.org/messagepickup/3.0";
+
+/// Delivery-request limit used when the caller doesn't give one.
+pub const DEFAULT_LIMIT: usize = 10;
+
+/// Upper bound on a single delivery-request.
+pub const MAX_LIMIT: usize = 100;
+
+/// Outcome of a pickup run.
+#[derive(Debug)]
+pub struct PickupResult {
+    pub events: Vec<PacketEvent>,
+    /// Body of the mediator's `status` reply.
+    pub status: Value,
+    /// One entry per delivered attachment (raw envelope + unpacked plaintext).
+    pub messages: Vec<Value>,
+    /// IDs acknowledged with `messages-received`.
+    pub acknowledged: Vec<String>,
+}
+
+/// Run a full pickup for `alias`: query the queue status, request up to
+/// `limit` messages and, if `acknowledge` is set, confirm their receipt so the
+/// mediator deletes them.
+pub async fn pickup(
+    state: &Arc<AppState>,
+    alias: &str,
+    limit: usize,
+    acknowledge: bool,
+) -> Result<PickupResult, FlowError> {
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(FlowError::Validation(format!(
+            "limit must be between 1 and {MAX_LIMIT}"
+        )));
+    }
+    let identity = state
+        .identity(alias)
+        .await
+        .ok_or_else(|| FlowError::unknown_alias("alias", alias))?;
+    let did = identity.did().to_string();
+
+    let correlation_id = Uuid::new_v4().to_string();
+    let mut events: Vec<PacketEvent> = Vec::new();
+
+    // ── Step 1: status-request → status ─────────────────────────────────
+    let status = exchange(
+        state,
+        &identity,
+        "status-request",
+        json!({ "recipient_did": &did }),
+        &correlation_id,
+        &mut events,
+    )
+    .await?;
+    let status_body = status.body.clone();
+    let queued = status_body
+        .get("message_count")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0);
+    info!("{alias} pickup status: {queued} message(s) queued");
+
+    // ── Step 2: delivery-request → delivery ─────────────────────────────
+    // An empty queue is answered with another status instead of a delivery
+    let mut messages = Vec::new();
+    let mut delivered_ids = Vec::new();
+    if queued > 0 {
+        let delivery = exchange(
+            state,
+            &identity,
+            "delivery-request",
+            json!({ "recipient_did": &did, "limit": limit }),
+            &correlation_id,
+            &mut events,
+        )
+        .await?;
+        if delivery.type_ == format!("{PICKUP_PROTOCOL}/delivery") {
+            for (id, packed) in delivered_envelopes(&delivery) {
+                let entry = unpack_delivered(state, &identity, &id, &packed).await;
+                messages.push(entry);
+                delivered_ids.push(id);
+            }
+        }
+        info!(
+            "{alias} pickup delivered {} message(s)",
+            delivered_ids.len()
+        );
+    }
+
+    // ── Step 3: messages-received → status ─────────────────────────────
+    let mut acknowledged = Vec::new();
+    if acknowledge && !delivered_ids.is_empty() {
+        exchange(
+            state,
+            &identity,
+            "messages-received",
+            json!({ "message_id_list": &delivered_ids }),
+            &correlation_id,
+            &mut events,
+        )
+        .await?;
+        acknowledged = delivered_ids;
+    }
+
+    Ok(PickupResult {
+        events,
+        status: status_body,
+        messages,
+        acknowledged,
+    })
+}
+
+/// Send one pickup request to `identity`'s mediator and wait for the reply,
+/// emitting both as packet events. Returns the unpacked reply.
+async fn exchange(
+    state: &Arc<AppState>,
+    identity: &Identity,
+    kind: &str,
+    body: Value,
+    correlation_id: &str,
+    events: &mut Vec<PacketEvent>,
+) -> Result<Message, FlowError> {
+    let atm = &*state.atm;
+    let did = identity.did();
+    let mediator_did = identity.mediator_did.as_str();
+    let alias = identity.alias().to_lowercase();
+
+    let now = unix_now();
+    let request = Message::build(
+        Uuid::new_v4().into(),
+        format!("{PICKUP_PROTOCOL}/{kind}"),
+        body,
+    )
+    .header("return_route".into(), json!("all"))
+    .to(mediator_did.to_string())
+    .from(did.to_string())
+    .created_time(now)
+    .expires_time(now + 300)
+    .finalize();
+    let request_id = request.id.clone();
+    state.inbound.track(&request_id, correlation_id);
+
+    let (packed, _) = atm
+        .pack_encrypted(&request, mediator_did, Some(did), Some(did), None)
+        .await
+        .map_err(|e| FlowError::Packing(format!("pack_encrypted {kind} failed: {e}")))?;
+
+    let packed_json = serde_json::from_str::<Value>(&packed).unwrap_or_else(|_| json!(&packed));
+    let envelope = state.decode_envelope(&packed_json).await;
+    let request_evt = PacketEvent::new(
+        PacketDirection::Outbound,
+        did,
+        mediator_did,
+        PacketStep::MessagePickup,
+        json!({
+            "message": serde_json::to_value(&request).unwrap_or_else(|_| json!({"id": &request_id})),
+            "packed": packed_json,
+        }),
+        Some(correlation_id.to_string()),
+    )
+    .with_aliases(&alias, "mediator")
+    .with_annotations(json!({ "envelope": envelope }));
+    let _ = state.packet_tx.send(request_evt.clone());
+    events.push(request_evt);
+
+    let reply = match atm
+        .send_message(&identity.profile, &packed, &request_id, true, false)
+        .await
+        .map_err(|e| FlowError::from_atm(&format!("{kind} failed"), e))?
+    {
+        SendMessageResponse::Message(reply) => *reply,
+        _ => {
+            return Err(FlowError::Transport(format!(
+                "{kind}: mediator returned no DIDComm reply"
+            )));
+        }
+    };
+    state.persist_message(
+        MessageDirection::Sent,
+        &request,
+        Some(&alias),
+        Some("mediator"),
+    );
+    state.persist_message(
+        MessageDirection::Received,
+        &reply,
+        Some("mediator"),
+        Some(&alias),
+    );
+
+    // Deliveries carry the queued envelopes; everything else is a status
+    let step = if reply.type_ == format!("{PICKUP_PROTOCOL}/delivery") {
+        PacketStep::MessageDelivery
+    } else {
+        PacketStep::MessagePickup
+    };
+    let reply_evt = PacketEvent::new(
+        PacketDirection::Inbound,
+        mediator_did,
+        did,
+        step,
+        json!({
+            "message": serde_json::to_value(&reply).unwrap_or_else(|_| json!({"id": &reply.id})),
+        }),
+        Some(correlation_id.to_string()),
+    )
+    .with_aliases("mediator", &alias);
+    let _ = state.packet_tx.send(reply_evt.clone());
+    events.push(reply_evt);
+
+    Ok(reply)
+}
+
+/// `(attachment id, packed envelope)` for every base64 attachment of a delivery.
+fn delivered_envelopes(delivery: &Message) -> Vec<(String, String)> {
+    delivery
+        .attachments
+        .iter()
+        .flatten()
+        .filter_map(|attachment| {
+            let AttachmentData::Base64 { value } = &attachment.data else {
+                warn!("Unsupported delivery attachment {:?}", attachment.id);
+                return None;
+            };
+            let id = attachment.id.clone().unwrap_or_default();
+            let Some(packed) =
+                decode_base64(&value.base64).and_then(|bytes| String::from_utf8(bytes).ok())
+            else {
+                warn!("Delivery attachment {id:?} is not a base64-encoded envelope");
+                return None;
+            };
+            Some((id, packed))
+        })
+        .collect()
+}
+
+/// Unpack one delivered envelope. Failures are reported back to the sender.
+async fn unpack_delivered(
+    state: &Arc<AppState>,
+    identity: &Identity,
+    id: &str,
+    packed: &str,
+) -> Value {
+    let raw = serde_json::from_str::<Value>(packed).unwrap_or_else(|_| json!(packed));
+    match state.atm.unpack(packed).await {
+        Ok((message, metadata)) => {
+            let from_alias = match &message.from {
+                Some(from) => state.alias_for_did(from).await,
+                None => None,
+            };
+            state.persist_message(
+                MessageDirection::Received,
+                &message,
+                from_alias.as_deref(),
+                Some(&identity.alias().to_lowercase()),
+            );
+            json!({
+                "id": id,
+                "packed": raw,
+                "message": serde_json::to_value(&message)
+                    .unwrap_or_else(|_| json!({"id": message.id})),
+                "unpack_metadata": metadata,
+            })
+        }
+        Err(e) => {
+            let reason = e.to_string();
+            let report =
+                problem_report::report_unpack_failure(state, identity, packed, id, &reason).await;
+            json!({
+                "id": id,
+                "packed": raw,
+                "unpack_error": reason,
+                "problem_report_sent": report.is_some(),
+            })
+        }
+    }
+}
@@ -1,2 +1,20 @@
+pub mod attachments;
+pub mod delete_messages;
+pub mod did_rotation;
+pub mod discover_features;
+pub mod file_transfer;
+pub mod message_pickup;
+pub mod oob;
+pub mod problem_report;
 pub mod send_message;
 pub mod trust_ping;
+
+use std::time::SystemTime;
+
+/// Seconds since the Unix epoch, as used by DIDComm `created_time`/`expires_time`.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
@@ -0,0 +1,230 @@
+/// Out-of-Band 2.0 flow — lets two parties that have never met bootstrap a
+/// DIDComm connection.
+///
+/// The inviter publishes an `invitation` (JSON or an `_oob` URL) naming its
+/// DID. The invitee opens the connection by sending its first message — a
+/// trust ping whose `pthid` is the invitation ID — back over the mediator.
+use std::env;
+use std::sync::Arc;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha256::digest;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::error::FlowError;
+use crate::flows::trust_ping;
+use crate::mediator::AppState;
+use crate::packet_logger::{PacketDirection, PacketEvent, PacketStep};
+
+pub const INVITATION_TYPE: &str = "https://didcomm.org/out-of-band/2.0/invitation";
+
+/// Media types an invitation says the inviter accepts.
+const ACCEPT: [&str; 1] = ["didcomm/v2"];
+
+/// Base URL `_oob` links are built on when `OOB_BASE_URL` is unset.
+const DEFAULT_BASE_URL: &str = "http://localhost:3000/invite";
+
+/// An out-of-band invitation as published by the inviter.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Invitation {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub id: String,
+    /// The inviter's DID — where the invitee sends its first message.
+    pub from: String,
+    pub body: InvitationBody,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvitationBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub goal_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub goal: Option<String>,
+    #[serde(default)]
+    pub accept: Vec<String>,
+    /// Protocols the inviter can speak once connected.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub protocols: Vec<String>,
+}
+
+impl Invitation {
+    /// `<base>?_oob=<base64url(invitation)>`.
+    pub fn to_url(&self) -> String {
+        let base = env::var("OOB_BASE_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string());
+        let encoded = URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default());
+        format!("{base}?_oob={encoded}")
+    }
+
+    /// Decode the invitation carried in an `_oob` URL.
+    pub fn from_url(url: &str) -> Result<Self, FlowError> {
+        let encoded = url
+            .split(['?', '&'])
+            .find_map(|param| param.strip_prefix("_oob="))
+            .ok_or_else(|| FlowError::Validation("URL has no _oob parameter".into()))?;
+        let bytes = URL_SAFE_NO_PAD
+            .decode(encoded.trim_end_matches('='))
+            .map_err(|e| FlowError::Validation(format!("_oob is not base64url: {e}")))?;
+        serde_json::from_slice(&bytes)
+            .map_err(|e| FlowError::Validation(format!("_oob is not an invitation: {e}")))
+    }
+
+    fn validate(&self) -> Result<(), FlowError> {
+        if self.type_ != INVITATION_TYPE {
+            return Err(FlowError::Validation(format!(
+                "unsupported invitation type {}",
+                self.type_
+            )));
+        }
+        if !self.body.accept.is_empty() && !self.body.accept.iter().any(|a| a == "didcomm/v2") {
+            return Err(FlowError::Validation(
+                "invitation does not accept didcomm/v2".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Create an invitation from `alias` and remember it so its acceptance can
+/// be authorised on the inviter's mediator account.
+pub async fn create_invitation(
+    state: &Arc<AppState>,
+    alias: &str,
+    goal_code: Option<String>,
+    goal: Option<String>,
+) -> Result<(PacketEvent, Invitation), FlowError> {
+    let inviter = state
+        .identity(alias)
+        .await
+        .ok_or_else(|| FlowError::unknown_alias("inviter", alias))?;
+
+    let protocols = state
+        .atm
+        .discover_features()
+        .get_discoverable_state()
+        .read()
+        .await
+        .protocols
+        .clone();
+    let invitation = Invitation {
+        type_: INVITATION_TYPE.to_string(),
+        id: Uuid::new_v4().to_string(),
+        from: inviter.did().to_string(),
+        body: InvitationBody {
+            goal_code,
+            goal,
+            accept: ACCEPT.iter().map(|a| a.to_string()).collect(),
+            protocols,
+        },
+    };
+    state
+        .invitations
+        .write()
+        .await
+        .insert(invitation.id.clone(), inviter.alias().to_string());
+
+    let evt = PacketEvent::new(
+        PacketDirection::Outbound,
+        inviter.did(),
+        "out-of-band",
+        PacketStep::OobInvitation,
+        json!({
+            "invitation": &invitation,
+            "url": invitation.to_url(),
+        }),
+        Some(invitation.id.clone()),
+    )
+    .with_aliases(&inviter.alias().to_lowercase(), "out-of-band");
+    let _ = state.packet_tx.send(evt.clone());
+    info!("{alias} created OOB invitation {}", invitation.id);
+    Ok((evt, invitation))
+}
+
+/// Accept `invitation` on behalf of `alias`: authorise the inviter on the
+/// invitee's mediator account (and, for invitations issued here, the invitee
+/// on the inviter's), then send a trust ping in the invitation's thread.
+///
+/// Returns the packet events and whether the inviter is managed by this demo.
+pub async fn accept_invitation(
+    state: &Arc<AppState>,
+    alias: &str,
+    invitation: &Invitation,
+) -> Result<(Vec<PacketEvent>, bool), FlowError> {
+    invitation.validate()?;
+    let invitee = state
+        .identity(alias)
+        .await
+        .ok_or_else(|| FlowError::unknown_alias("invitee", alias))?;
+    if invitation.from == invitee.did() {
+        return Err(FlowError::Validation(
+            "an identity cannot accept its own invitation".into(),
+        ));
+    }
+
+    let received_evt = PacketEvent::new(
+        PacketDirection::Inbound,
+        "out-of-band",
+        invitee.did(),
+        PacketStep::OobInvitation,
+        json!({ "invitation": invitation }),
+        Some(invitation.id.clone()),
+    )
+    .with_aliases("out-of-band", &invitee.alias().to_lowercase());
+    let _ = state.packet_tx.send(received_evt.clone());
+
+    // ── Authorise both ends of the new connection ───────────────────────
+    let inviter_hash = digest(&invitation.from);
+    state
+        .grant_access_hashes(&invitee, &[inviter_hash.as_str()])
+        .await
+        .map_err(|e| FlowError::from_atm("access_list_add", e))?;
+
+    let issued_by = state.invitations.read().await.get(&invitation.id).cloned();
+    let inviter = match issued_by {
+        Some(inviter_alias) => state.identity(&inviter_alias).await,
+        None => None,
+    };
+    if let Some(inviter) = &inviter {
+        state
+            .grant_access(inviter, &[&invitee])
+            .await
+            .map_err(|e| FlowError::from_atm("access_list_add", e))?;
+    }
+    let inviter_label = inviter
+        .as_ref()
+        .map(|i| i.alias().to_lowercase())
+        .unwrap_or_else(|| "inviter".to_string());
+
+    // ── First message: a trust ping threaded under the invitation ───────
+    info!("{alias} accepted OOB invitation {} from {inviter_label}", invitation.id);
+    let mut events = vec![received_evt];
+    events.extend(
+        trust_ping::ping_did(
+            state,
+            &invitee,
+            &invitation.from,
+            &inviter_label,
+            Some(&invitation.id),
+        )
+        .await?,
+    );
+    Ok((events, inviter.is_some()))
+}
+
+/// Parse an invitation supplied either as JSON or as an `_oob` URL.
+pub fn parse_invitation(
+    invitation: Option<Value>,
+    url: Option<&str>,
+) -> Result<Invitation, FlowError> {
+    match (invitation, url) {
+        (Some(json), _) => serde_json::from_value(json)
+            .map_err(|e| FlowError::Validation(format!("malformed invitation: {e}"))),
+        (None, Some(url)) => Invitation::from_url(url),
+        (None, None) => Err(FlowError::Validation(
+            "either invitation or url is required".into(),
+        )),
+    }
+}
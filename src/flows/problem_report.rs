@@ -0,0 +1,184 @@
+/// Problem Report 2.0 — parses inbound `problem-report` messages and sends our
+/// own when an inbound message can't be processed (failed unpack, expired).
+///
+/// Reports start their own thread and point at the problematic one through
+/// `pthid`, so the inbound router hands them to whichever flow is waiting on it.
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde_json::{Value, json};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use affinidi_messaging_didcomm::Message;
+use affinidi_messaging_sdk::messages::problem_report::ProblemReport;
+
+use crate::error::FlowError;
+use crate::flows::attachments::decode_base64;
+use crate::flows::unix_now;
+use crate::identity::Identity;
+use crate::inbound;
+use crate::mediator::AppState;
+use crate::packet_logger::PacketStep;
+
+pub const PROBLEM_REPORT_TYPE: &str = "https://didcomm.org/report-problem/2.0/problem-report";
+
+/// Cryptographic operation failed (the message could not be unpacked).
+pub const CODE_TRUST_CRYPTO: &str = "e.p.trust.crypto";
+/// The message arrived after its `expires_time`.
+pub const CODE_REQ_TIME: &str = "e.p.req.time";
+
+/// A parsed problem report.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProblemReportInfo {
+    pub id: String,
+    /// Sorter, scope and descriptor (e.g. `e.p.trust.crypto`).
+    pub code: String,
+    pub comment: String,
+    /// `comment` with its `{n}` placeholders filled in from `args`.
+    pub message: String,
+    pub args: Vec<String>,
+    pub escalate_to: Option<String>,
+    pub thid: Option<String>,
+    /// ID of the thread (or message) the problem is about.
+    pub pthid: Option<String>,
+}
+
+impl ProblemReportInfo {
+    /// Parse `msg` if it is a well-formed problem report.
+    pub fn parse(msg: &Message) -> Option<Self> {
+        if msg.type_ != PROBLEM_REPORT_TYPE {
+            return None;
+        }
+        let body: ProblemReport = serde_json::from_value(msg.body.clone()).ok()?;
+        Some(Self {
+            id: msg.id.clone(),
+            message: body.interpolation(),
+            code: body.code,
+            comment: body.comment,
+            args: body.args,
+            escalate_to: body.escalate_to,
+            thid: msg.thid.clone(),
+            pthid: msg.pthid.clone(),
+        })
+    }
+
+    pub fn to_value(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_else(|_| json!({"code": self.code}))
+    }
+}
+
+/// Send a problem report from `identity` to `to` about the thread `pthid`.
+pub async fn send_problem_report(
+    state: &Arc<AppState>,
+    identity: &Identity,
+    to: &str,
+    body: ProblemReport,
+    pthid: &str,
+    correlation_id: Option<&str>,
+) -> Result<Message, FlowError> {
+    let code = body.code.clone();
+    let report = Message::build(
+        Uuid::new_v4().into(),
+        PROBLEM_REPORT_TYPE.to_string(),
+        json!(body),
+    )
+    .to(to.to_string())
+    .from(identity.did().to_string())
+    .pthid(pthid.to_string())
+    .created_time(unix_now())
+    .finalize();
+
+    inbound::send_reply(
+        state,
+        identity,
+        &report,
+        to,
+        PacketStep::ProblemReport,
+        correlation_id,
+    )
+    .await?;
+    info!("{} sent problem report {code} to {to}", identity.alias());
+    Ok(report)
+}
+
+/// True if `msg` carries an `expires_time` that has already passed.
+pub fn is_expired(msg: &Message) -> bool {
+    msg.expires_time
+        .is_some_and(|expires| expires <= unix_now())
+}
+
+/// Tell the sender of an expired message that it was not processed.
+pub async fn report_expired(
+    state: &Arc<AppState>,
+    identity: &Identity,
+    msg: &Message,
+    correlation_id: Option<&str>,
+) {
+    let Some(sender) = msg.from.as_deref() else {
+        return;
+    };
+    let body = ProblemReport {
+        code: CODE_REQ_TIME.to_string(),
+        comment: "Message {1} expired at {2}".to_string(),
+        args: vec![
+            msg.id.clone(),
+            msg.expires_time.unwrap_or_default().to_string(),
+        ],
+        escalate_to: None,
+    };
+    let pthid = msg.thid.as_deref().unwrap_or(&msg.id);
+    if let Err(e) = send_problem_report(state, identity, sender, body, pthid, correlation_id).await
+    {
+        warn!("Failed to report expired message {}: {e}", msg.id);
+    }
+}
+
+/// Report a packed message that `identity` failed to unpack back to its
+/// sender — only possible for authcrypt envelopes, whose protected header
+/// names the sender key (`skid`).
+///
+/// That `skid` is unauthenticated (the envelope didn't unpack), so reports
+/// only go to one of our own identities or a DID `identity` already has as a
+/// contact. Otherwise anyone could aim our reports at an arbitrary DID.
+pub async fn report_unpack_failure(
+    state: &Arc<AppState>,
+    identity: &Identity,
+    packed: &str,
+    msg_ref: &str,
+    error: &str,
+) -> Option<Message> {
+    let sender = sender_kid(packed)?;
+    let sender_did = sender.split('#').next().unwrap_or(&sender).to_string();
+    if !state.contacts.knows(identity.alias(), &sender_did)
+        && state.alias_for_did(&sender_did).await.is_none()
+    {
+        debug!(
+            "{} not reporting unpack failure to unknown sender {sender_did}",
+            identity.alias()
+        );
+        return None;
+    }
+
+    let body = ProblemReport {
+        code: CODE_TRUST_CRYPTO.to_string(),
+        comment: "Message {1} could not be unpacked - {2}".to_string(),
+        args: vec![msg_ref.to_string(), error.to_string()],
+        escalate_to: None,
+    };
+    match send_problem_report(state, identity, &sender_did, body, msg_ref, None).await {
+        Ok(report) => Some(report),
+        Err(e) => {
+            warn!("Failed to report unpack failure to {sender_did}: {e}");
+            None
+        }
+    }
+}
+
+/// Sender key ID from a JWE's protected header, if present.
+fn sender_kid(packed: &str) -> Option<String> {
+    let envelope: Value = serde_json::from_str(packed).ok()?;
+    let protected = envelope.get("protected")?.as_str()?;
+    let header: Value = serde_json::from_slice(&decode_base64(protected)?).ok()?;
+    header.get("skid")?.as_str().map(|s| s.to_string())
+}
@@ -1,19 +1,119 @@
-/// Full annotated send-message flow: Alice → Mediator → Bob (or vice versa).
+/// Full annotated send-message flow: sender → Mediator → recipient (e.g. Alice → Bob).
 ///
 /// Each step emits a `PacketEvent` to the broadcast channel so the frontend's
 /// Packet Inspector can show the exact bytes on the wire.
 use std::sync::Arc;
-use std::time::SystemTime;
 
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use tracing::{debug, error, info};
 use uuid::Uuid;
 
-use affinidi_messaging_didcomm::Message;
-use affinidi_messaging_sdk::profiles::ATMProfile;
-
+use affinidi_messaging_didcomm::{Attachment, Message, PackEncryptedOptions};
+use crate::error::FlowError;
+use crate::flows::attachments::{self, AttachmentSpec};
+use crate::flows::unix_now;
+use crate::identity::{Identity, IdentityRegistry};
 use crate::mediator::AppState;
 use crate::packet_logger::{PacketDirection, PacketEvent, PacketStep};
+use crate::store::MessageDirection;
+
+/// How the plaintext is protected before it is routed through the mediator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SendMode {
+    /// ECDH-1PU authenticated encryption: the recipient learns (repudiably) who sent it.
+    #[default]
+    Authcrypt,
+    /// ECDH-ES anonymous encryption with no signature.
+    Anoncrypt,
+    /// JWS only — integrity and non-repudiation but no confidentiality.
+    Signed,
+    /// JWS nested inside an anoncrypt JWE: only the recipient can verify the sender.
+    SignThenAnoncrypt,
+    /// No protection at all (`application/didcomm-plain+json`).
+    Plaintext,
+}
+
+impl SendMode {
+    fn signs(self) -> bool {
+        matches!(self, Self::Signed | Self::SignThenAnoncrypt)
+    }
+
+    fn encrypts(self) -> bool {
+        matches!(self, Self::Authcrypt | Self::Anoncrypt | Self::SignThenAnoncrypt)
+    }
+
+    /// True if the recipient can cryptographically attribute the message to the sender.
+    fn authenticates_sender(self) -> bool {
+        !matches!(self, Self::Anoncrypt | Self::Plaintext)
+    }
+}
+
+/// Lifetime of a sent message when the request doesn't set one.
+pub const DEFAULT_EXPIRY_SECS: u64 = 300;
+
+/// When a sent message expires (`expires_time`).
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Expiry {
+    /// `DEFAULT_EXPIRY_SECS` from now.
+    #[default]
+    Default,
+    /// No `expires_time` header at all.
+    Never,
+    /// Seconds from now — negative values produce an already-stale message.
+    In(i64),
+}
+
+/// Per-request options for `send_message`.
+#[derive(Debug, Clone, Default)]
+pub struct SendOptions {
+    pub mode: SendMode,
+    /// Omit the sender entirely: no `from` in the plaintext, no `skid` in the
+    /// JWE and an anonymous forward envelope.
+    pub anonymous: bool,
+    /// Message being replied to — the reply joins its thread.
+    pub reply_to: Option<String>,
+    /// Thread to post in (defaults to the `reply_to` message's thread).
+    pub thid: Option<String>,
+    /// Parent thread, when this message starts a sub-thread.
+    pub pthid: Option<String>,
+    pub expiry: Expiry,
+    /// DIDComm attachments (inline base64, inline JSON or links).
+    pub attachments: Vec<AttachmentSpec>,
+}
+
+impl SendOptions {
+    /// Mode actually used once `anonymous` is applied — authcrypt needs the
+    /// sender's key so falls back to anoncrypt, and signed modes are rejected
+    /// because the signature identifies the sender.
+    pub fn effective_mode(&self) -> Result<SendMode, FlowError> {
+        match (self.anonymous, self.mode) {
+            (false, mode) => Ok(mode),
+            (true, SendMode::Authcrypt) => Ok(SendMode::Anoncrypt),
+            (true, mode) if mode.signs() => Err(FlowError::Validation(
+                "anonymous messages cannot be signed".into(),
+            )),
+            (true, mode) => Ok(mode),
+        }
+    }
+
+    /// Thread the message belongs to: the explicit `thid`, else the thread of
+    /// the `reply_to` message (or that message's ID if it was never seen).
+    fn thread_id(&self, state: &AppState) -> Result<Option<String>, FlowError> {
+        let replied_thread = self
+            .reply_to
+            .as_ref()
+            .map(|id| state.threads.thread_of(id).unwrap_or_else(|| id.clone()));
+        match (&self.thid, replied_thread) {
+            (Some(thid), Some(replied)) if *thid != replied => Err(FlowError::Validation(
+                format!("reply_to message belongs to thread {replied}, not {thid}"),
+            )),
+            (Some(thid), _) => Ok(Some(thid.clone())),
+            (None, replied) => Ok(replied),
+        }
+    }
+}
 
 /// Execute the full send flow and return the events that were emitted.
 pub async fn send_message(
@@ -21,37 +121,68 @@ pub async fn send_message(
     from_alias: &str,
     to_alias: &str,
     body_text: &str,
-) -> Result<Vec<PacketEvent>, String> {
+    options: &SendOptions,
+) -> Result<Vec<PacketEvent>, FlowError> {
+    let mode = options.effective_mode()?;
+    let anonymous = options.anonymous;
+    let thid = options.thread_id(state)?;
     let correlation_id = Uuid::new_v4().to_string();
     let mut events: Vec<PacketEvent> = Vec::new();
 
-    // Resolve sender / recipient profiles
-    let (sender_profile, recipient_profile, sender_did, recipient_did, recipient_mediator_did) =
-        resolve_profiles(state, from_alias, to_alias)?;
-
-    let sender_profile = &sender_profile;
-    let recipient_profile = &recipient_profile;
+    // Resolve sender / recipient identities
+    let (sender, recipient) = resolve_profiles(state, from_alias, to_alias).await?;
+    let sender_profile = &sender.profile;
+    let sender_did = sender.did().to_string();
+    let recipient_did = recipient.did().to_string();
+    let recipient_mediator_did = recipient.mediator_did.clone();
 
     let atm = &*state.atm;
+    // Signing and plaintext packing go straight through the DIDComm crate with
+    // the TDK's resolvers (the ATM only exposes `pack_encrypted`)
+    let tdk = state.tdk.get_shared_state();
 
     // ── Step 1: Build plaintext message ─────────────────────────────────
-    let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
+    let now = unix_now();
 
-    let msg = Message::build(
+    let mut builder = Message::build(
         Uuid::new_v4().into(),
         "https://didcomm.org/basicmessage/2.0/message".into(),
         json!({ "content": body_text }),
     )
     .to(recipient_did.clone())
-    .from(sender_did.clone())
-    .created_time(now)
-    .expires_time(now + 300) // 5 min expiry
-    .finalize();
+    .created_time(now);
+    match options.expiry {
+        Expiry::Default => builder = builder.expires_time(now + DEFAULT_EXPIRY_SECS),
+        Expiry::Never => {}
+        Expiry::In(secs) => builder = builder.expires_time(now.saturating_add_signed(secs)),
+    }
+    if let Some(thid) = &thid {
+        builder = builder.thid(thid.clone());
+    }
+    if let Some(pthid) = &options.pthid {
+        builder = builder.pthid(pthid.clone());
+    }
+    if !anonymous {
+        builder = builder.from(sender_did.clone());
+        // Announce a DID rotation until the next one replaces it
+        if let Some(from_prior) = &sender.from_prior {
+            builder = builder.from_prior(from_prior.clone());
+        }
+    }
+    let attachments = options
+        .attachments
+        .iter()
+        .enumerate()
+        .map(|(i, spec)| spec.build(i))
+        .collect::<Result<Vec<_>, _>>()?;
+    if !attachments.is_empty() {
+        builder = builder.attachments(attachments.clone());
+    }
+    let msg = builder.finalize();
 
     let msg_id = msg.id.clone();
+    state.inbound.track(&msg_id, &correlation_id);
+    let mut packed: Option<String> = None;
     let plaintext_json: Value =
         serde_json::to_value(&msg).unwrap_or_else(|_| json!({"error": "serialisation failed"}));
 
@@ -63,54 +194,161 @@ pub async fn send_message(
         plaintext_json.clone(),
         Some(correlation_id.clone()),
     );
+    let mut notes = serde_json::Map::new();
+    if anonymous {
+        notes.insert("anonymous".into(), json!(true));
+        notes.insert(
+            "detail".into(),
+            json!("No `from` header — nothing in the message identifies the sender."),
+        );
+    } else if msg.from_prior.is_some() {
+        notes.insert("from_prior".into(), json!(true));
+        notes.insert(
+            "detail".into(),
+            json!("Carries a `from_prior` JWT proving the sender rotated from its previous DID."),
+        );
+    }
+    // Sizes before encryption; the envelope steps report what they grow to
+    let plaintext_bytes = serde_json::to_vec(&msg).map_or(0, |v| v.len());
+    let (attachment_summary, attachments_bytes) = attachments::summarize(&attachments);
+    if !attachments.is_empty() {
+        notes.insert("attachments".into(), attachment_summary);
+        notes.insert(
+            "size".into(),
+            json!({
+                "plaintext_bytes": plaintext_bytes,
+                "attachments_bytes": attachments_bytes,
+            }),
+        );
+    }
+    let evt = if notes.is_empty() {
+        evt
+    } else {
+        evt.with_annotations(Value::Object(notes))
+    };
     debug!("{} → {} plaintext: {}", from_alias, to_alias, plaintext_json);
     let _ = state.packet_tx.send(evt.clone());
     events.push(evt);
 
-    // ── Step 2: Pack encrypted + signed ─────────────────────────────────
-    let packed_msg = atm
-        .pack_encrypted(
-            &msg,
+    // ── Step 2: Sign (JWS) ──────────────────────────────────────────────
+    if mode.signs() {
+        let (jws, meta) = msg
+            .pack_signed(&sender_did, &tdk.did_resolver, &tdk.secrets_resolver)
+            .await
+            .map_err(|e| FlowError::Packing(format!("pack_signed failed: {e}")))?;
+
+        let signed_json: Value =
+            serde_json::from_str(&jws).unwrap_or_else(|_| json!({"raw": &jws}));
+        let envelope = state.decode_envelope(&signed_json).await;
+        let detail = if mode == SendMode::Signed {
+            "The payload is only base64url-encoded: the mediator can read the message and verify the sender."
+        } else {
+            "Shown for inspection — the signed plaintext is nested inside the anoncrypt JWE below."
+        };
+        let evt = PacketEvent::new(
+            PacketDirection::Outbound,
+            &sender_did,
             &recipient_did,
-            Some(&sender_did),
-            Some(&sender_did),
-            None,
+            PacketStep::SignedEnvelope,
+            signed_json,
+            Some(correlation_id.clone()),
         )
-        .await
-        .map_err(|e| format!("pack_encrypted failed: {e}"))?;
+        .with_annotations(with_size(
+            json!({
+                "mode": mode,
+                "sign_by_kid": meta.sign_by_kid,
+                "detail": detail,
+                "envelope": envelope,
+            }),
+            &attachments,
+            plaintext_bytes,
+            jws.len(),
+        ));
+        debug!("Signed envelope for {to_alias}: {} bytes", jws.len());
+        let _ = state.packet_tx.send(evt.clone());
+        events.push(evt);
 
-    let encrypted_json: Value = serde_json::from_str(&packed_msg.0)
-        .unwrap_or_else(|_| json!({"raw": packed_msg.0}));
+        if mode == SendMode::Signed {
+            packed = Some(jws);
+        }
+    }
 
-    let evt = PacketEvent::new(
-        PacketDirection::Outbound,
-        &sender_did,
-        &recipient_did,
-        PacketStep::EncryptedPayload,
-        encrypted_json.clone(),
-        Some(correlation_id.clone()),
-    );
-    debug!("Encrypted payload for {to_alias}: {} bytes", packed_msg.0.len());
-    let _ = state.packet_tx.send(evt.clone());
-    events.push(evt);
+    // ── Step 3: Encrypt (JWE) ───────────────────────────────────────────
+    if mode.encrypts() {
+        // The flow adds the routing layer itself (step 4), so don't let the
+        // packer wrap the JWE in a forward envelope of its own
+        let options = PackEncryptedOptions {
+            forward: false,
+            ..PackEncryptedOptions::default()
+        };
+        let from = (mode == SendMode::Authcrypt).then_some(sender_did.as_str());
+        let sign_by = mode.signs().then_some(sender_did.as_str());
+        let (jwe, meta) = atm
+            .pack_encrypted(&msg, &recipient_did, from, sign_by, Some(&options))
+            .await
+            .map_err(|e| FlowError::Packing(format!("pack_encrypted failed: {e}")))?;
+
+        let encrypted_json: Value =
+            serde_json::from_str(&jwe).unwrap_or_else(|_| json!({"raw": &jwe}));
+        let envelope = state.decode_envelope(&encrypted_json).await;
+        let evt = PacketEvent::new(
+            PacketDirection::Outbound,
+            &sender_did,
+            &recipient_did,
+            PacketStep::EncryptedPayload,
+            encrypted_json,
+            Some(correlation_id.clone()),
+        )
+        .with_annotations(with_size(
+            json!({
+                "mode": mode,
+                "from_kid": meta.from_kid,
+                "sign_by_kid": meta.sign_by_kid,
+                "to_kids": meta.to_kids,
+                // authcrypt puts the sender key ID (`skid`) in the cleartext protected header
+                "sender_kid_exposed": from.is_some(),
+                "sender_authenticated": mode.authenticates_sender(),
+                "anonymous": anonymous,
+                "recipient_can_attribute": mode.authenticates_sender() && !anonymous,
+                "envelope": envelope,
+            }),
+            &attachments,
+            plaintext_bytes,
+            jwe.len(),
+        ));
+        debug!("Encrypted payload for {to_alias}: {} bytes", jwe.len());
+        let _ = state.packet_tx.send(evt.clone());
+        events.push(evt);
+
+        packed = Some(jwe);
+    }
 
-    // ── Step 3: Wrap in forward envelope for the mediator ───────────────
+    let packed = match packed {
+        Some(packed) => packed,
+        None => msg
+            .pack_plaintext(&tdk.did_resolver)
+            .await
+            .map_err(|e| FlowError::Packing(format!("pack_plaintext failed: {e}")))?,
+    };
+
+    // ── Step 4: Wrap in forward envelope for the mediator ───────────────
     let (_forward_id, forward_msg) = atm
         .routing()
         .forward_message(
             sender_profile,
-            false,
-            &packed_msg.0,
+            anonymous,
+            &packed,
             &recipient_mediator_did,
             &recipient_did,
             None,
             None,
         )
         .await
-        .map_err(|e| format!("forward_message failed: {e}"))?;
+        .map_err(|e| FlowError::Forwarding(format!("forward_message failed: {e}")))?;
 
     let forward_json: Value = serde_json::from_str(&forward_msg)
         .unwrap_or_else(|_| json!({"raw": forward_msg}));
+    let envelope = state.decode_envelope(&forward_json).await;
 
     let evt = PacketEvent::new(
         PacketDirection::Outbound,
@@ -119,12 +357,23 @@ pub async fn send_message(
         PacketStep::EncryptedForward,
         forward_json.clone(),
         Some(correlation_id.clone()),
-    );
+    )
+    .with_annotations(json!({
+        "mode": mode,
+        // What the mediator finds once it decrypts the forward and reads the attachment
+        "mediator_can_read_content": !mode.encrypts(),
+        "mediator_can_verify_sender": mode == SendMode::Signed,
+        // An anonymous forward names no sender, but it is still submitted over the
+        // sender's authenticated mediator session
+        "forward_names_sender": !anonymous,
+        "mediator_knows_sender": true,
+        "envelope": envelope,
+    }));
     debug!("Forward envelope → mediator: {} bytes", forward_msg.len());
     let _ = state.packet_tx.send(evt.clone());
     events.push(evt);
 
-    // ── Step 4: Send to mediator ────────────────────────────────────────
+    // ── Step 5: Send to mediator ────────────────────────────────────────
     let evt = PacketEvent::new(
         PacketDirection::Outbound,
         &sender_did,
@@ -142,7 +391,7 @@ pub async fn send_message(
     {
         Ok(response) => {
             let ack_json =
-                serde_json::to_value(&format!("{:?}", response)).unwrap_or(json!("ok"));
+                serde_json::to_value(format!("{:?}", response)).unwrap_or(json!("ok"));
             let evt = PacketEvent::new(
                 PacketDirection::Inbound,
                 "mediator",
@@ -151,69 +400,61 @@ pub async fn send_message(
                 json!({ "status": "stored", "response": ack_json }),
                 Some(correlation_id.clone()),
             );
-            info!("{from_alias} sent message {msg_id} to mediator");
+            info!("{from_alias} → {to_alias}: message {msg_id} stored by mediator");
+            // Recorded under registry keys, as inbound messages are
+            let from_key = IdentityRegistry::key(sender.alias());
+            let to_key = IdentityRegistry::key(recipient.alias());
+            let from_label = (!anonymous).then_some(from_key.as_str());
+            state.threads.record(&msg, from_label, Some(&to_key), false);
+            state.persist_message(MessageDirection::Sent, &msg, from_label, Some(&to_key));
             let _ = state.packet_tx.send(evt.clone());
             events.push(evt);
         }
         Err(e) => {
             error!("send_message failed: {e}");
-            return Err(format!("send_message failed: {e}"));
+            return Err(FlowError::from_atm("send_message failed", e));
         }
     }
 
-    // ── Step 5: Delivery confirmed ──────────────────────────────────────
-    // The mediator ACK means the message is stored and will be delivered
-    // to the recipient via their live WebSocket stream. We don't call
-    // live_stream_next here because it may pick up protocol messages
-    // (e.g. mediator status) instead of the actual forwarded message.
-    let evt = PacketEvent::new(
-        PacketDirection::Inbound,
-        "mediator",
-        &recipient_did,
-        PacketStep::MessageDelivery,
-        json!({
-            "msg_id": &msg_id,
-            "status": "delivered",
-            "detail": "Stored by mediator — will be delivered via live WebSocket stream"
-        }),
-        Some(correlation_id.clone()),
-    )
-    .with_aliases(from_alias, to_alias);
-    info!("{from_alias} → {to_alias}: message {msg_id} delivered to mediator");
-    let _ = state.packet_tx.send(evt.clone());
-    events.push(evt);
-
+    // Delivery to the recipient is reported asynchronously by their inbound
+    // listener (MessagePickup / MessageDelivery), tagged with this correlation ID.
     Ok(events)
 }
 
-/// Resolve aliases ("alice"/"bob") to (sender_profile, recipient_profile, sender_did, recipient_did, recipient_mediator_did).
-fn resolve_profiles(
+/// Add a `size` block to an envelope's annotations when the message carries
+/// attachments, showing how much the plaintext grew once wrapped.
+fn with_size(
+    mut annotations: Value,
+    attachments: &[Attachment],
+    plaintext_bytes: usize,
+    envelope_bytes: usize,
+) -> Value {
+    if !attachments.is_empty() {
+        annotations["size"] = json!({
+            "plaintext_bytes": plaintext_bytes,
+            "envelope_bytes": envelope_bytes,
+            "overhead_bytes": envelope_bytes.saturating_sub(plaintext_bytes),
+        });
+    }
+    annotations
+}
+
+/// Resolve aliases to (sender, recipient) identities via the registry.
+async fn resolve_profiles(
     state: &Arc<AppState>,
     from: &str,
     to: &str,
-) -> Result<(Arc<ATMProfile>, Arc<ATMProfile>, String, String, String), String> {
-    let from_lower = from.to_lowercase();
-    let to_lower = to.to_lowercase();
-
-    let (sender_profile, sender_did) = match from_lower.as_str() {
-        "alice" => (state.alice_profile.clone(), state.alice_info.did.clone()),
-        "bob" => (state.bob_profile.clone(), state.bob_info.did.clone()),
-        _ => return Err(format!("Unknown sender: {from}")),
-    };
+) -> Result<(Identity, Identity), FlowError> {
+    let registry = state.identities.read().await;
 
-    let (recipient_profile, recipient_did, recipient_mediator_did) = match to_lower.as_str() {
-        "alice" => (
-            state.alice_profile.clone(),
-            state.alice_info.did.clone(),
-            state.alice_mediator_did.clone(),
-        ),
-        "bob" => (
-            state.bob_profile.clone(),
-            state.bob_info.did.clone(),
-            state.bob_mediator_did.clone(),
-        ),
-        _ => return Err(format!("Unknown recipient: {to}")),
-    };
+    let sender = registry
+        .get(from)
+        .cloned()
+        .ok_or_else(|| FlowError::unknown_alias("sender", from))?;
+    let recipient = registry
+        .get(to)
+        .cloned()
+        .ok_or_else(|| FlowError::unknown_alias("recipient", to))?;
 
-    Ok((sender_profile, recipient_profile, sender_did, recipient_did, recipient_mediator_did))
+    Ok((sender, recipient))
 }
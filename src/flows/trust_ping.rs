@@ -1,75 +1,118 @@
 /// Trust Ping flow — sends a DIDComm trust-ping and captures the pong response.
 ///
 /// Emits `PacketEvent`s for both the outbound ping and inbound pong so the
-/// Packet Inspector can visualise the round-trip.
+/// Packet Inspector can visualise the round-trip. The pong is picked up by the
+/// sender's inbound listener and handed back to this flow by thread ID.
 use std::sync::Arc;
 use std::time::Duration;
 
 use serde_json::json;
-use tracing::{debug, error, info};
+use sha256::digest;
+use tracing::{debug, info};
 use uuid::Uuid;
 
+use crate::error::FlowError;
+use crate::flows::problem_report::{PROBLEM_REPORT_TYPE, ProblemReportInfo};
+use crate::identity::{Identity, IdentityRegistry};
 use crate::mediator::AppState;
 use crate::packet_logger::{PacketDirection, PacketEvent, PacketStep};
+use crate::store::MessageDirection;
+
+/// How long to wait for the pong after the ping was accepted by the mediator.
+const PONG_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Send a trust-ping from `from_alias` to `to_alias` and wait for the pong.
 pub async fn trust_ping(
     state: &Arc<AppState>,
     from_alias: &str,
     to_alias: &str,
-) -> Result<Vec<PacketEvent>, String> {
+) -> Result<Vec<PacketEvent>, FlowError> {
+    // Resolve profiles — "mediator" targets the sender's own mediator
+    let (sender, target_did) = {
+        let registry = state.identities.read().await;
+        let sender = registry
+            .get(from_alias)
+            .cloned()
+            .ok_or_else(|| FlowError::unknown_alias("sender", from_alias))?;
+        let target_did = if to_alias.eq_ignore_ascii_case("mediator") {
+            sender.mediator_did.clone()
+        } else {
+            registry
+                .get(to_alias)
+                .map(|t| t.did().to_string())
+                .ok_or_else(|| FlowError::unknown_alias("ping target", to_alias))?
+        };
+        (sender, target_did)
+    };
+    ping_did(state, &sender, &target_did, to_alias, None).await
+}
+
+/// Send a trust-ping from `sender` to `target_did` (labelled `to_alias` in
+/// logs) and wait for the pong. `pthid` places the ping in a parent thread,
+/// e.g. the out-of-band invitation it answers.
+pub async fn ping_did(
+    state: &Arc<AppState>,
+    sender: &Identity,
+    target_did: &str,
+    to_alias: &str,
+    pthid: Option<&str>,
+) -> Result<Vec<PacketEvent>, FlowError> {
     let correlation_id = Uuid::new_v4().to_string();
     let mut events: Vec<PacketEvent> = Vec::new();
     let atm = &*state.atm;
-
-    // Resolve profiles
-    let (sender_profile, sender_did, target_did) = match from_alias.to_lowercase().as_str() {
-        "alice" => {
-            let target = match to_alias.to_lowercase().as_str() {
-                "bob" => state.bob_info.did.clone(),
-                "mediator" => state.alice_mediator_did.clone(),
-                _ => return Err(format!("Unknown ping target: {to_alias}")),
-            };
-            (state.alice_profile.clone(), state.alice_info.did.clone(), target)
-        }
-        "bob" => {
-            let target = match to_alias.to_lowercase().as_str() {
-                "alice" => state.alice_info.did.clone(),
-                "mediator" => state.bob_mediator_did.clone(),
-                _ => return Err(format!("Unknown ping target: {to_alias}")),
-            };
-            (state.bob_profile.clone(), state.bob_info.did.clone(), target)
-        }
-        _ => return Err(format!("Unknown sender: {from_alias}")),
-    };
-    let sender_profile = &sender_profile;
+    let from_alias = sender.alias();
+    let target_did = target_did.to_string();
+    let sender_did = sender.did().to_string();
+    let sender_profile = &sender.profile;
 
     // ── Step 1: Send Ping ──────────────────────────────────────────────
+    let mut ping = atm
+        .trust_ping()
+        .generate_ping_message(Some(&sender_did), &target_did, true)
+        .map_err(|e| FlowError::Packing(format!("generate_ping_message failed: {e}")))?;
+    ping.pthid = pthid.map(str::to_string);
+    let ping_id = ping.id.clone();
+    state.inbound.track(&ping_id, &correlation_id);
+
+    // Register for the pong before sending so a fast reply can't be missed
+    let pong_rx = state.inbound.expect_reply(&ping_id);
+
     let ping_evt = PacketEvent::new(
         PacketDirection::Outbound,
         &sender_did,
         &target_did,
         PacketStep::TrustPing,
-        json!({
-            "type": "https://didcomm.org/trust-ping/2.0/ping",
-            "from": &sender_did,
-            "to": &target_did,
-            "body": { "response_requested": true }
-        }),
+        serde_json::to_value(&ping).unwrap_or_else(|_| json!({"id": &ping_id})),
         Some(correlation_id.clone()),
     );
     let _ = state.packet_tx.send(ping_evt.clone());
     events.push(ping_evt);
 
-    let response = atm
-        .trust_ping()
-        .send_ping(sender_profile, &target_did, true, true, false)
-        .await
-        .map_err(|e| format!("send_ping failed: {e}"))?;
+    let send_result = async {
+        let (packed, _) = atm
+            .pack_encrypted(&ping, &target_did, Some(&sender_did), Some(&sender_did), None)
+            .await
+            .map_err(|e| FlowError::Packing(format!("pack_encrypted failed: {e}")))?;
+        atm.send_message(sender_profile, &packed, &ping_id, false, true)
+            .await
+            .map_err(|e| FlowError::from_atm("send_ping failed", e))?;
+        Ok::<_, FlowError>(packed)
+    }
+    .await;
+    let packed = match send_result {
+        Ok(packed) => packed,
+        Err(e) => {
+            state.inbound.cancel_reply(&ping_id);
+            return Err(e);
+        }
+    };
 
-    info!(
-        "{from_alias} → {to_alias} PING sent (hash: {})",
-        response.message_hash
+    info!("{from_alias} → {to_alias} PING sent ({ping_id})");
+    state.persist_message(
+        MessageDirection::Sent,
+        &ping,
+        Some(&IdentityRegistry::key(from_alias)),
+        Some(&IdentityRegistry::key(to_alias)),
     );
 
     let ack_evt = PacketEvent::new(
@@ -78,67 +121,58 @@ pub async fn trust_ping(
         &sender_did,
         PacketStep::MediatorAck,
         json!({
-            "message_hash": &response.message_hash,
-            "message_id": &response.message_id,
+            "message_hash": digest(&packed),
+            "message_id": &ping_id,
         }),
         Some(correlation_id.clone()),
     );
     let _ = state.packet_tx.send(ack_evt.clone());
     events.push(ack_evt);
 
-    // ── Step 2: Receive Pong via live stream ────────────────────────────
-    // Retry up to 3 times to skip mediator protocol messages (e.g. status)
-    let mut pong_received = false;
-    for attempt in 0..3 {
-        match atm
-            .message_pickup()
-            .live_stream_next(sender_profile, Some(Duration::from_secs(10)), true)
-            .await
-        {
-            Ok(Some((msg, _metadata))) => {
-                // Skip non-ping-response messages (mediator status, etc.)
-                if msg.type_ != "https://didcomm.org/trust-ping/2.0/ping-response" {
-                    debug!("Skipping non-pong message (type: {}, attempt {attempt})", msg.type_);
-                    continue;
-                }
-                let pong_json =
-                    serde_json::to_value(&msg).unwrap_or_else(|_| json!({"id": msg.id}));
-                let pong_evt = PacketEvent::new(
-                    PacketDirection::Inbound,
-                    &target_did,
-                    &sender_did,
-                    PacketStep::TrustPong,
-                    pong_json,
-                    Some(correlation_id.clone()),
-                );
-                info!("{from_alias} ← {to_alias} PONG received");
-                let _ = state.packet_tx.send(pong_evt.clone());
-                events.push(pong_evt);
-                pong_received = true;
-                break;
-            }
-            Ok(None) => {
-                debug!("No pong received within timeout (attempt {attempt})");
-                break; // timeout means no more messages
-            }
-            Err(e) => {
-                error!("Pong pickup failed: {e}");
-                break;
-            }
+    // ── Step 2: Receive Pong via the sender's inbound listener ──────────
+    match tokio::time::timeout(PONG_TIMEOUT, pong_rx).await {
+        // Already emitted as a ProblemReport event by the inbound listener
+        Ok(Ok(inbound)) if inbound.message.type_ == PROBLEM_REPORT_TYPE => {
+            let (code, comment) = ProblemReportInfo::parse(&inbound.message)
+                .map(|r| (r.code, r.message))
+                .unwrap_or_else(|| ("unknown".into(), "malformed problem report".into()));
+            return Err(FlowError::from_problem_report(&code, &comment));
+        }
+        Ok(Ok(inbound)) => {
+            let pong_json = json!({
+                "message": serde_json::to_value(&inbound.message)
+                    .unwrap_or_else(|_| json!({"id": inbound.message.id})),
+                "unpack_metadata": &*inbound.metadata,
+            });
+            let pong_evt = PacketEvent::new(
+                PacketDirection::Inbound,
+                &target_did,
+                &sender_did,
+                PacketStep::TrustPong,
+                pong_json,
+                Some(correlation_id.clone()),
+            );
+            info!("{from_alias} ← {to_alias} PONG received");
+            let _ = state.packet_tx.send(pong_evt.clone());
+            events.push(pong_evt);
+        }
+        _ => {
+            state.inbound.cancel_reply(&ping_id);
+            debug!("No pong received within {PONG_TIMEOUT:?}");
+            let timeout_evt = PacketEvent::new(
+                PacketDirection::Inbound,
+                &target_did,
+                &sender_did,
+                PacketStep::TrustPong,
+                json!({ "status": "timeout", "detail": "Pong not received within timeout" }),
+                Some(correlation_id.clone()),
+            );
+            let _ = state.packet_tx.send(timeout_evt);
+            return Err(FlowError::Timeout(format!(
+                "No pong from {to_alias} within {}s",
+                PONG_TIMEOUT.as_secs()
+            )));
         }
-    }
-
-    if !pong_received {
-        let timeout_evt = PacketEvent::new(
-            PacketDirection::Inbound,
-            &target_did,
-            &sender_did,
-            PacketStep::TrustPong,
-            json!({ "status": "timeout", "detail": "Pong not received (may have been consumed by protocol messages)" }),
-            Some(correlation_id.clone()),
-        );
-        let _ = state.packet_tx.send(timeout_evt.clone());
-        events.push(timeout_evt);
     }
 
     Ok(events)
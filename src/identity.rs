@@ -1,37 +1,312 @@
-/// Identity management for Alice and Bob using TDK profiles.
+/// Identity management using TDK profiles.
 ///
 /// Wraps the Affinidi TDK profile system — identities are loaded from
-/// the `environments.json` file produced by `setup_environment`.
-use serde::Serialize;
+/// the `environments.json` file produced by `setup_environment` and kept
+/// in an alias-keyed `IdentityRegistry`.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use affinidi_messaging_sdk::{
+    profiles::ATMProfile, protocols::mediator::acls::AccessListModeType,
+};
+use affinidi_tdk::{
+    common::{errors::TDKError, profiles::TDKProfile},
+    dids::{DID, KeyType, PeerKeyRole},
+};
+use affinidi_encoding::{
+    ED25519_PUB, P256_PUB, P384_PUB, P521_PUB, SECP256K1_PUB, X25519_PUB,
+    decode_multikey_with_codec,
+};
+use affinidi_tdk::did_common::{
+    Document,
+    verification_method::{VerificationMethod, VerificationRelationship},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::FlowError;
+
+/// Key set used for every did:peer this demo generates — P256 + Ed25519
+/// (verification) and X25519 + Secp256k1 (encryption).
+pub const PEER_KEY_SET: [(PeerKeyRole, KeyType); 4] = [
+    (PeerKeyRole::Verification, KeyType::P256),
+    (PeerKeyRole::Verification, KeyType::Ed25519),
+    (PeerKeyRole::Encryption, KeyType::X25519),
+    (PeerKeyRole::Encryption, KeyType::Secp256k1),
+];
+
+/// Aliases with special meaning in flows and packet events ("unknown" and
+/// "anonymous" label senders that aren't one of our identities).
+pub const RESERVED_ALIASES: [&str; 5] = ["mediator", "system", "all", "unknown", "anonymous"];
 
 /// Public identity information exposed to the frontend.
-#[derive(Debug, Clone, Serialize)]
+///
+/// Everything except `alias` and `mediator_did` comes from the resolved DID document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IdentityInfo {
     pub alias: String,
     pub did: String,
     pub mediator_did: Option<String>,
+    /// Distinct key types held by the DID ("P-256", "Ed25519", ...), in document order.
     pub key_types: Vec<String>,
+    pub verification_methods: Vec<VerificationKeyInfo>,
+    /// Key IDs usable for authentication (signing).
+    pub authentication: Vec<String>,
+    /// Key IDs usable for key agreement (encryption).
+    pub key_agreement: Vec<String>,
+    pub services: Vec<ServiceInfo>,
+}
+
+/// A single verification method from the DID document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationKeyInfo {
+    pub id: String,
+    /// Verification method type (e.g. "Multikey", "JsonWebKey2020").
+    pub method_type: String,
+    /// Curve / key type if it could be determined from the key material.
+    pub key_type: Option<String>,
+    /// Verification relationships referencing this key ("authentication", "keyAgreement", ...).
+    pub purposes: Vec<String>,
+}
+
+/// A service entry from the DID document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceInfo {
+    pub id: Option<String>,
+    pub types: Vec<String>,
+    /// Every `uri` found in the service endpoint.
+    pub uris: Vec<String>,
+    /// True if one of the URIs is this identity's mediator DID.
+    pub routes_via_mediator: bool,
+    pub endpoint: Value,
 }
 
 impl IdentityInfo {
-    /// Build an `IdentityInfo` from a TDK profile reference and the resolved ATM profile.
-    pub fn from_profile(
+    /// Build an `IdentityInfo` from the DID document resolved for `did`.
+    pub fn from_document(
         alias: &str,
         did: &str,
         mediator_did: Option<&str>,
+        doc: &Document,
     ) -> Self {
+        let relationships: [(&str, &Vec<VerificationRelationship>); 5] = [
+            ("authentication", &doc.authentication),
+            ("assertionMethod", &doc.assertion_method),
+            ("keyAgreement", &doc.key_agreement),
+            ("capabilityInvocation", &doc.capability_invocation),
+            ("capabilityDelegation", &doc.capability_delegation),
+        ];
+
+        let verification_methods: Vec<VerificationKeyInfo> = doc
+            .verification_method
+            .iter()
+            .map(|vm| {
+                let id = vm.id.to_string();
+                let purposes = relationships
+                    .iter()
+                    .filter(|(_, rels)| rels.iter().any(|r| refers_to(r.get_id(), &id)))
+                    .map(|(name, _)| name.to_string())
+                    .collect();
+                VerificationKeyInfo {
+                    key_type: key_type(vm),
+                    method_type: vm.type_.clone(),
+                    purposes,
+                    id,
+                }
+            })
+            .collect();
+
+        let mut key_types: Vec<String> = Vec::new();
+        for kt in verification_methods.iter().filter_map(|vm| vm.key_type.as_ref()) {
+            if !key_types.contains(kt) {
+                key_types.push(kt.clone());
+            }
+        }
+
+        let services = doc
+            .service
+            .iter()
+            .map(|svc| {
+                let endpoint = serde_json::to_value(&svc.service_endpoint).unwrap_or(Value::Null);
+                let uris = endpoint_uris(&endpoint);
+                ServiceInfo {
+                    id: svc.id.as_ref().map(|u| u.to_string()),
+                    types: svc.type_.clone(),
+                    routes_via_mediator: mediator_did
+                        .is_some_and(|m| uris.iter().any(|u| u == m)),
+                    uris,
+                    endpoint,
+                }
+            })
+            .collect();
+
+        let ids = |rels: &[VerificationRelationship]| -> Vec<String> {
+            rels.iter().map(|r| r.get_id().to_string()).collect()
+        };
+
         Self {
             alias: alias.to_string(),
             did: did.to_string(),
             mediator_did: mediator_did.map(|s| s.to_string()),
-            // Key types are inferred from the did:peer method — each profile
-            // typically carries P256 + Ed25519 (verification) and X25519 + Secp256k1 (encryption)
-            key_types: vec![
-                "P-256".into(),
-                "Ed25519".into(),
-                "X25519".into(),
-                "secp256k1".into(),
-            ],
+            key_types,
+            verification_methods,
+            authentication: ids(&doc.authentication),
+            key_agreement: ids(&doc.key_agreement),
+            services,
+        }
+    }
+}
+
+/// True if a verification relationship reference (absolute or `#fragment`) points at `vm_id`.
+fn refers_to(reference: &str, vm_id: &str) -> bool {
+    reference == vm_id || (reference.starts_with('#') && vm_id.ends_with(reference))
+}
+
+/// Determine the key type from `publicKeyMultibase` (multicodec prefix) or `publicKeyJwk` (`crv`).
+fn key_type(vm: &VerificationMethod) -> Option<String> {
+    if let Some(multibase) = vm.property_set.get("publicKeyMultibase").and_then(Value::as_str) {
+        return multikey_type(multibase).map(|t| t.to_string());
+    }
+
+    vm.property_set
+        .get("publicKeyJwk")
+        .and_then(|jwk| jwk.get("crv"))
+        .and_then(Value::as_str)
+        .map(|crv| crv.to_string())
+}
+
+/// Key type named by the multicodec prefix of a multibase-encoded public key.
+pub fn multikey_type(multibase: &str) -> Option<&'static str> {
+    let (codec, _) = decode_multikey_with_codec(multibase).ok()?;
+    match codec {
+        P256_PUB => Some("P-256"),
+        P384_PUB => Some("P-384"),
+        P521_PUB => Some("P-521"),
+        ED25519_PUB => Some("Ed25519"),
+        X25519_PUB => Some("X25519"),
+        SECP256K1_PUB => Some("secp256k1"),
+        _ => None,
+    }
+}
+
+/// Collect `uri` values from a service endpoint (plain string, map, or array of maps).
+fn endpoint_uris(endpoint: &Value) -> Vec<String> {
+    match endpoint {
+        Value::String(uri) => vec![uri.clone()],
+        Value::Object(map) => map
+            .get("uri")
+            .and_then(Value::as_str)
+            .map(|u| vec![u.to_string()])
+            .unwrap_or_default(),
+        Value::Array(items) => items.iter().flat_map(endpoint_uris).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Generate a fresh did:peer with `PEER_KEY_SET` whose DIDComm service routes
+/// through `mediator_did`, returning a TDK profile carrying its secrets.
+pub fn generate_peer_profile(alias: &str, mediator_did: &str) -> Result<TDKProfile, TDKError> {
+    let (did, secrets) =
+        DID::generate_did_peer(PEER_KEY_SET.to_vec(), Some(mediator_did.to_string()))?;
+
+    Ok(TDKProfile {
+        alias: alias.to_string(),
+        did,
+        mediator: Some(mediator_did.to_string()),
+        secrets,
+    })
+}
+
+/// An activated identity: its ATM profile handle plus public metadata.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub profile: Arc<ATMProfile>,
+    pub info: IdentityInfo,
+    /// Mediator DID this identity receives messages through (needed for forwarding).
+    pub mediator_did: String,
+    /// SHA-256 hash of the DID as known by the mediator (used in access lists).
+    pub did_hash: String,
+    /// Access list mode of the mediator account.
+    pub acl_mode: AccessListModeType,
+    /// True if created via the API rather than loaded from `environments.json`.
+    pub runtime: bool,
+    /// Signed `from_prior` JWT attached to outgoing messages after a DID rotation.
+    pub from_prior: Option<String>,
+}
+
+impl Identity {
+    pub fn did(&self) -> &str {
+        &self.info.did
+    }
+
+    pub fn alias(&self) -> &str {
+        &self.info.alias
+    }
+}
+
+/// Registry of active identities keyed by lower-cased alias.
+#[derive(Debug, Default)]
+pub struct IdentityRegistry {
+    identities: BTreeMap<String, Identity>,
+}
+
+impl IdentityRegistry {
+    /// Normalise an alias into its registry key ("Alice" → "alice").
+    pub fn key(alias: &str) -> String {
+        alias.trim().to_lowercase()
+    }
+
+    /// Check that `alias` can name an identity: non-empty and not reserved.
+    pub fn validate_alias(alias: &str) -> Result<(), FlowError> {
+        let alias = alias.trim();
+        if alias.is_empty() {
+            return Err(FlowError::Validation("alias cannot be empty".into()));
         }
+        if RESERVED_ALIASES
+            .iter()
+            .any(|r| alias.eq_ignore_ascii_case(r))
+        {
+            return Err(FlowError::Validation(format!(
+                "Alias '{alias}' is reserved"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Insert (or replace) an identity. Returns the previous entry if any.
+    pub fn insert(&mut self, identity: Identity) -> Option<Identity> {
+        self.identities.insert(Self::key(&identity.info.alias), identity)
+    }
+
+    pub fn contains(&self, alias: &str) -> bool {
+        self.identities.contains_key(&Self::key(alias))
+    }
+
+    /// Remove an identity by alias.
+    pub fn remove(&mut self, alias: &str) -> Option<Identity> {
+        self.identities.remove(&Self::key(alias))
+    }
+
+    /// Case-insensitive alias lookup.
+    pub fn get(&self, alias: &str) -> Option<&Identity> {
+        self.identities.get(&Self::key(alias))
+    }
+
+    /// Find the identity that owns `did`.
+    pub fn find_by_did(&self, did: &str) -> Option<&Identity> {
+        self.identities.values().find(|i| i.info.did == did)
+    }
+
+    /// Every registered identity in alias order.
+    pub fn all(&self) -> impl Iterator<Item = &Identity> {
+        self.identities.values()
+    }
+
+    /// Public metadata for every identity, keyed by lower-cased alias.
+    pub fn infos(&self) -> BTreeMap<String, IdentityInfo> {
+        self.identities
+            .iter()
+            .map(|(k, v)| (k.clone(), v.info.clone()))
+            .collect()
     }
 }
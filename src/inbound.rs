@@ -0,0 +1,573 @@
+/// Inbound listener — one background task per identity that drains the
+/// mediator's live WebSocket stream and emits real pickup/delivery events.
+///
+/// Flows register the IDs of the messages they send so inbound replies can be
+/// tagged with the originating `correlation_id`, and can wait for a reply in a
+/// given thread instead of pulling from the live stream themselves.
+///
+/// Envelopes the stream can't unpack are found by an idle-time sweep of the
+/// queue and reported to their sender (see `problem_report`).
+///
+/// A listener deletes each message from the mediator queue as it arrives, so
+/// while it runs the REST fetch, Message Pickup and deletes find the queue
+/// empty. Pausing an identity's listener leaves its messages queued for those
+/// endpoints (replies that flows wait for, like pongs, then time out).
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::json;
+use tokio::sync::oneshot;
+use tokio::task::AbortHandle;
+use tracing::{debug, info, warn};
+
+use affinidi_messaging_didcomm::{Message, UnpackMetadata};
+use affinidi_messaging_sdk::messages::fetch::FetchOptions;
+use affinidi_messaging_sdk::messages::{DeleteMessageRequest, FetchDeletePolicy};
+use affinidi_messaging_sdk::protocols::discover_features::DiscoverFeaturesQuery;
+
+use crate::error::FlowError;
+use crate::flows::did_rotation;
+use crate::flows::file_transfer;
+use crate::flows::discover_features::QUERIES_TYPE;
+use crate::flows::problem_report::{self, ProblemReportInfo};
+use crate::flows::unix_now;
+use crate::identity::Identity;
+use crate::mediator::AppState;
+use crate::packet_logger::{PacketDirection, PacketEvent, PacketStep};
+use crate::store::MessageDirection;
+
+/// How long a single `live_stream_next` call waits before re-polling.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Back-off after a stream error (e.g. WebSocket reconnecting).
+const ERROR_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Queued messages checked per sweep for envelopes the stream dropped.
+const SWEEP_LIMIT: usize = 20;
+
+/// Maximum number of message → correlation mappings kept in memory.
+const MAX_CORRELATIONS: usize = 4096;
+
+const TRUST_PING_TYPE: &str = "https://didcomm.org/trust-ping/2.0/ping";
+pub const BASIC_MESSAGE_TYPE: &str = "https://didcomm.org/basicmessage/2.0/message";
+
+/// A message received by one of the identities.
+#[derive(Debug)]
+pub struct InboundMessage {
+    pub message: Message,
+    pub metadata: Box<UnpackMetadata>,
+}
+
+/// Bounded message-ID → correlation-ID map (oldest entries evicted first).
+#[derive(Default)]
+struct Correlations {
+    map: HashMap<String, String>,
+    order: VecDeque<String>,
+}
+
+/// Shared bookkeeping between flows and the per-identity listeners.
+#[derive(Default)]
+pub struct InboundRouter {
+    correlations: Mutex<Correlations>,
+    waiters: Mutex<HashMap<String, oneshot::Sender<InboundMessage>>>,
+    listeners: Mutex<HashMap<String, AbortHandle>>,
+    /// Lower-cased aliases whose listener is paused.
+    paused: Mutex<HashSet<String>>,
+}
+
+impl InboundRouter {
+    /// Remember that `message_id` was sent as part of `correlation_id`.
+    pub fn track(&self, message_id: &str, correlation_id: &str) {
+        let mut c = self.correlations.lock().unwrap();
+        if c
+            .map
+            .insert(message_id.to_string(), correlation_id.to_string())
+            .is_none()
+        {
+            c.order.push_back(message_id.to_string());
+        }
+        while c.order.len() > MAX_CORRELATIONS {
+            if let Some(oldest) = c.order.pop_front() {
+                c.map.remove(&oldest);
+            }
+        }
+    }
+
+    /// Find the correlation ID for an inbound message by thread, parent thread or message ID.
+    pub fn correlation_for(&self, msg: &Message) -> Option<String> {
+        let c = self.correlations.lock().unwrap();
+        [msg.thid.as_deref(), msg.pthid.as_deref(), Some(msg.id.as_str())]
+            .into_iter()
+            .flatten()
+            .find_map(|id| c.map.get(id).cloned())
+    }
+
+    /// Register interest in the first reply whose `thid` is `thread_id`.
+    pub fn expect_reply(&self, thread_id: &str) -> oneshot::Receiver<InboundMessage> {
+        let (tx, rx) = oneshot::channel();
+        self.waiters
+            .lock()
+            .unwrap()
+            .insert(thread_id.to_string(), tx);
+        rx
+    }
+
+    /// Drop a reply registration (e.g. after the caller timed out).
+    pub fn cancel_reply(&self, thread_id: &str) {
+        self.waiters.lock().unwrap().remove(thread_id);
+    }
+
+    /// Hand `inbound` to a flow waiting on its thread (or, for problem reports,
+    /// its parent thread), or give it back if nobody is waiting.
+    fn dispatch(&self, inbound: InboundMessage) -> Option<InboundMessage> {
+        let waiter = {
+            let mut waiters = self.waiters.lock().unwrap();
+            [inbound.message.thid.as_deref(), inbound.message.pthid.as_deref()]
+                .into_iter()
+                .flatten()
+                .find_map(|id| waiters.remove(id))
+        };
+        match waiter {
+            Some(tx) => tx.send(inbound).err(),
+            None => Some(inbound),
+        }
+    }
+
+    /// Stop the listener task for `alias`, if one is running.
+    pub fn stop_listener(&self, alias: &str) {
+        if let Some(handle) = self.listeners.lock().unwrap().remove(&alias.to_lowercase()) {
+            handle.abort();
+        }
+    }
+
+    pub fn is_paused(&self, alias: &str) -> bool {
+        self.paused.lock().unwrap().contains(&alias.to_lowercase())
+    }
+
+    /// Forget a pause (the identity was removed).
+    pub fn clear_pause(&self, alias: &str) {
+        self.paused.lock().unwrap().remove(&alias.to_lowercase());
+    }
+}
+
+/// Pause or resume `identity`'s listener. Pausing stops the task, so nothing
+/// drains the identity's mediator queue until it is resumed.
+pub fn pause_listener(state: &Arc<AppState>, identity: &Identity, paused: bool) {
+    let alias = identity.alias().to_lowercase();
+    if paused {
+        state.inbound.paused.lock().unwrap().insert(alias.clone());
+        state.inbound.stop_listener(&alias);
+        info!("Inbound listener paused for {}", identity.alias());
+    } else if state.inbound.paused.lock().unwrap().remove(&alias) {
+        spawn_listener(state, identity);
+    }
+}
+
+/// Spawn (or replace) the background listener for `identity`, unless it is
+/// paused.
+pub fn spawn_listener(state: &Arc<AppState>, identity: &Identity) {
+    if state.inbound.is_paused(identity.alias()) {
+        info!("Inbound listener for {} stays paused", identity.alias());
+        return;
+    }
+    let handle = tokio::spawn(listen(state.clone(), identity.clone()));
+    if let Some(old) = state
+        .inbound
+        .listeners
+        .lock()
+        .unwrap()
+        .insert(identity.alias().to_lowercase(), handle.abort_handle())
+    {
+        old.abort();
+    }
+    info!("Inbound listener started for {}", identity.alias());
+}
+
+async fn listen(state: Arc<AppState>, identity: Identity) {
+    let atm = state.atm.clone();
+    loop {
+        match atm
+            .message_pickup()
+            .live_stream_next(&identity.profile, Some(POLL_INTERVAL), true)
+            .await
+        {
+            Ok(Some((msg, metadata))) => handle_message(&state, &identity, msg, metadata).await,
+            Ok(None) => sweep_undecryptable(&state, &identity).await,
+            Err(e) => {
+                warn!("{} live stream error: {e}", identity.alias());
+                tokio::time::sleep(ERROR_BACKOFF).await;
+            }
+        }
+    }
+}
+
+/// The SDK drops envelopes it can't unpack from the live stream, so they stay
+/// queued on the mediator. When the stream is idle, find those leftovers,
+/// report them to their sender and delete them. Messages that do unpack are
+/// left alone for the stream.
+async fn sweep_undecryptable(state: &Arc<AppState>, identity: &Identity) {
+    let opts = FetchOptions {
+        limit: SWEEP_LIMIT,
+        delete_policy: FetchDeletePolicy::DoNotDelete,
+        start_id: None,
+    };
+    let queued = match state.atm.fetch_messages(&identity.profile, &opts).await {
+        Ok(response) => response.success,
+        Err(e) => {
+            debug!("{} queue sweep failed: {e}", identity.alias());
+            return;
+        }
+    };
+
+    let mut undecryptable = Vec::new();
+    for m in &queued {
+        let Some(packed) = &m.msg else {
+            continue;
+        };
+        if let Err(e) = state.atm.unpack(packed).await {
+            let reason = e.to_string();
+            warn!(
+                "{} could not unpack queued message {}: {reason}",
+                identity.alias(),
+                m.msg_id
+            );
+            problem_report::report_unpack_failure(state, identity, packed, &m.msg_id, &reason)
+                .await;
+            undecryptable.push(m.msg_id.clone());
+        }
+    }
+    if undecryptable.is_empty() {
+        return;
+    }
+    let request = DeleteMessageRequest {
+        message_ids: undecryptable,
+    };
+    if let Err(e) = state
+        .atm
+        .delete_messages_direct(&identity.profile, &request)
+        .await
+    {
+        warn!(
+            "{} failed to delete undecryptable messages: {e}",
+            identity.alias()
+        );
+    }
+}
+
+async fn handle_message(
+    state: &Arc<AppState>,
+    identity: &Identity,
+    msg: Message,
+    metadata: Box<UnpackMetadata>,
+) {
+    let recipient_did = identity.did();
+    let recipient_alias = identity.alias().to_lowercase();
+    let correlation_id = state.inbound.correlation_for(&msg);
+    let (sender_did, sender_alias) = match &msg.from {
+        Some(from) => (from.clone(), alias_of(state, identity, from).await),
+        None => ("anonymous".to_string(), "anonymous".to_string()),
+    };
+
+    debug!("{recipient_alias} ← {sender_alias}: {} ({})", msg.type_, msg.id);
+
+    // ── Pickup: the mediator handed us an envelope ──────────────────────
+    let evt = PacketEvent::new(
+        PacketDirection::Inbound,
+        "mediator",
+        recipient_did,
+        PacketStep::MessagePickup,
+        json!({
+            "msg_id": &msg.id,
+            "type": &msg.type_,
+            "sha256_hash": &metadata.sha256_hash,
+            "via": "live_stream",
+        }),
+        correlation_id.clone(),
+    )
+    .with_aliases("mediator", &recipient_alias);
+    let _ = state.packet_tx.send(evt);
+    state.persist_message(
+        MessageDirection::Received,
+        &msg,
+        Some(&sender_alias),
+        Some(&recipient_alias),
+    );
+
+    // ── Delivery: the decrypted plaintext as the recipient sees it ──────
+    // Problem reports get their own step, annotated with the parsed report
+    let report = ProblemReportInfo::parse(&msg);
+
+    // ── Expired: dropped unprocessed, optionally reported to the sender ─
+    if report.is_none() && problem_report::is_expired(&msg) {
+        let now = unix_now();
+        let expires_time = msg.expires_time.unwrap_or_default();
+        let evt = PacketEvent::new(
+            PacketDirection::Inbound,
+            &sender_did,
+            recipient_did,
+            PacketStep::MessageExpired,
+            json!({
+                "message": serde_json::to_value(&msg).unwrap_or_else(|_| json!({"id": msg.id})),
+                "unpack_metadata": &*metadata,
+            }),
+            correlation_id.clone(),
+        )
+        .with_aliases(&sender_alias, &recipient_alias)
+        .with_annotations(json!({
+            "expires_time": expires_time,
+            "received_at": now,
+            "expired_for_secs": now.saturating_sub(expires_time),
+            "problem_report": state.report_expired && msg.from.is_some(),
+            "detail": "Arrived after its expires_time — dropped without processing.",
+        }));
+        warn!("{recipient_alias} dropped expired {} from {sender_alias}", msg.id);
+        let _ = state.packet_tx.send(evt);
+        if state.report_expired {
+            problem_report::report_expired(state, identity, &msg, correlation_id.as_deref())
+                .await;
+        }
+        return;
+    }
+
+    let (step, annotations) = match &report {
+        Some(report) => (PacketStep::ProblemReport, report.to_value()),
+        None => (
+            PacketStep::MessageDelivery,
+            json!({
+                "sender_attributable": msg.from.is_some(),
+                "sender_authenticated": metadata.authenticated || metadata.non_repudiation,
+                "anonymous_sender": metadata.anonymous_sender,
+            }),
+        ),
+    };
+    let evt = PacketEvent::new(
+        PacketDirection::Inbound,
+        &sender_did,
+        recipient_did,
+        step,
+        json!({
+            "message": serde_json::to_value(&msg).unwrap_or_else(|_| json!({"id": msg.id})),
+            "unpack_metadata": &*metadata,
+        }),
+        correlation_id.clone(),
+    )
+    .with_aliases(&sender_alias, &recipient_alias)
+    .with_annotations(annotations);
+    match &report {
+        Some(report) => warn!(
+            "{recipient_alias} received problem report {} from {sender_alias}: {}",
+            report.code, report.message
+        ),
+        None => info!("{recipient_alias} received {} from {sender_alias}", msg.type_),
+    }
+    // File chunks are reported as transfer progress rather than one delivery each
+    if msg.type_ != file_transfer::CHUNK_TYPE {
+        let _ = state.packet_tx.send(evt);
+    }
+
+    // ── Contacts: follow DID rotations announced via from_prior ─────────
+    let contact_alias = (sender_alias != "unknown").then_some(sender_alias.as_str());
+    if let Some((claims, annotations, valid)) =
+        did_rotation::validate(msg.from.as_deref(), &metadata)
+    {
+        let evt = PacketEvent::new(
+            PacketDirection::Inbound,
+            &claims.iss,
+            recipient_did,
+            PacketStep::DidRotation,
+            json!({
+                "from_prior": &msg.from_prior,
+                "claims": &claims,
+            }),
+            correlation_id.clone(),
+        )
+        .with_aliases(&sender_alias, &recipient_alias)
+        .with_annotations(annotations);
+        let _ = state.packet_tx.send(evt);
+        if valid {
+            match state.contacts.rotate(
+                &recipient_alias,
+                &claims.iss,
+                &claims.sub,
+                contact_alias,
+            ) {
+                Some((_, known)) => info!(
+                    "{recipient_alias} updated contact {sender_alias}: {} → {} (previously known: {known})",
+                    claims.iss, claims.sub
+                ),
+                None => debug!(
+                    "{recipient_alias} already follows {sender_alias}'s rotation to {}",
+                    claims.sub
+                ),
+            }
+        } else {
+            warn!("{recipient_alias} rejected DID rotation from {sender_did}");
+        }
+    } else if msg.from.is_some() {
+        state
+            .contacts
+            .seen(&recipient_alias, &sender_did, contact_alias);
+    }
+
+    match msg.type_.as_str() {
+        BASIC_MESSAGE_TYPE => state.threads.record(
+            &msg,
+            contact_alias,
+            Some(&recipient_alias),
+            true,
+        ),
+        TRUST_PING_TYPE => respond_to_ping(state, identity, &msg, correlation_id.as_deref()).await,
+        QUERIES_TYPE => respond_to_query(state, identity, &msg, correlation_id.as_deref()).await,
+        file_transfer::MANIFEST_TYPE | file_transfer::CHUNK_TYPE => file_transfer::receive(
+            state,
+            identity,
+            &msg,
+            &sender_alias,
+            correlation_id.as_deref(),
+        ),
+        _ => {}
+    }
+
+    let inbound = InboundMessage {
+        message: msg,
+        metadata,
+    };
+    if let Some(unclaimed) = state.inbound.dispatch(inbound) {
+        debug!("No flow waiting for {}", unclaimed.message.id);
+    }
+}
+
+/// Answer a trust ping that asked for a response.
+async fn respond_to_ping(
+    state: &Arc<AppState>,
+    identity: &Identity,
+    ping: &Message,
+    correlation_id: Option<&str>,
+) {
+    let response_requested = ping
+        .body
+        .get("response_requested")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let Some(pinger) = ping.from.as_deref().filter(|_| response_requested) else {
+        return;
+    };
+
+    match state
+        .atm
+        .trust_ping()
+        .generate_pong_message(ping, Some(identity.did()))
+    {
+        Ok(pong) => {
+            if let Err(e) =
+                send_reply(state, identity, &pong, pinger, PacketStep::TrustPong, correlation_id)
+                    .await
+            {
+                warn!("Failed to answer ping {}: {e}", ping.id);
+            }
+        }
+        Err(e) => warn!("Failed to build pong for {}: {e}", ping.id),
+    }
+}
+
+/// Answer a discover-features query from the ATM's discoverable state.
+async fn respond_to_query(
+    state: &Arc<AppState>,
+    identity: &Identity,
+    query: &Message,
+    correlation_id: Option<&str>,
+) {
+    let Some(querier) = query.from.as_deref() else {
+        return;
+    };
+    let body: DiscoverFeaturesQuery = match serde_json::from_value(query.body.clone()) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Malformed discover-features query {}: {e}", query.id);
+            return;
+        }
+    };
+
+    let features = state.atm.discover_features();
+    let disclosure = features
+        .get_discoverable_state()
+        .read()
+        .await
+        .calculate_disclosure(&body);
+    match features.generate_disclosure_message(identity.did(), querier, query, Some(disclosure)) {
+        Ok(disclose) => {
+            if let Err(e) = send_reply(
+                state,
+                identity,
+                &disclose,
+                querier,
+                PacketStep::FeaturesDisclose,
+                correlation_id,
+            )
+            .await
+            {
+                warn!("Failed to answer query {}: {e}", query.id);
+            }
+        }
+        Err(e) => warn!("Failed to build disclosure for {}: {e}", query.id),
+    }
+}
+
+/// Pack `reply` for `to`, send it through `identity`'s mediator and emit it as `step`.
+pub async fn send_reply(
+    state: &Arc<AppState>,
+    identity: &Identity,
+    reply: &Message,
+    to: &str,
+    step: PacketStep,
+    correlation_id: Option<&str>,
+) -> Result<(), FlowError> {
+    let atm = &state.atm;
+    let did = identity.did();
+    if let Some(correlation_id) = correlation_id {
+        state.inbound.track(&reply.id, correlation_id);
+    }
+
+    let (packed, _) = atm
+        .pack_encrypted(reply, to, Some(did), Some(did), None)
+        .await
+        .map_err(|e| FlowError::Packing(format!("pack_encrypted failed: {e}")))?;
+    atm.send_message(&identity.profile, &packed, &reply.id, false, true)
+        .await
+        .map_err(|e| FlowError::from_atm("send_message failed", e))?;
+
+    let to_alias = alias_of(state, identity, to).await;
+    state.persist_message(
+        MessageDirection::Sent,
+        reply,
+        Some(&identity.alias().to_lowercase()),
+        Some(&to_alias),
+    );
+    let evt = PacketEvent::new(
+        PacketDirection::Outbound,
+        did,
+        to,
+        step,
+        serde_json::to_value(reply).unwrap_or_else(|_| json!({"id": reply.id})),
+        correlation_id.map(|c| c.to_string()),
+    )
+    .with_aliases(&identity.alias().to_lowercase(), &to_alias);
+    info!("{} answered {to_alias} with {}", identity.alias(), reply.type_);
+    let _ = state.packet_tx.send(evt);
+    Ok(())
+}
+
+/// Human-readable alias for `did` as seen from `identity`.
+async fn alias_of(state: &AppState, identity: &Identity, did: &str) -> String {
+    if did == identity.mediator_did {
+        return "mediator".to_string();
+    }
+    state
+        .identities
+        .read()
+        .await
+        .find_by_did(did)
+        .map(|i| i.alias().to_lowercase())
+        .unwrap_or_else(|| "unknown".to_string())
+}
@@ -1,18 +1,32 @@
 mod api;
+mod archive;
+mod contacts;
+mod did_peer;
+mod envelope;
+mod error;
 mod flows;
 mod identity;
+mod inbound;
 mod mediator;
 mod packet_logger;
+mod replay;
+mod store;
+mod threads;
+mod transfers;
 
 use std::env;
 use std::net::SocketAddr;
+use std::sync::Arc;
 
-use axum::{Router, routing::{get, post}};
+use axum::{Router, extract::DefaultBodyLimit, routing::{delete, get, post}};
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::services::ServeDir;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
+/// Largest session archive accepted by `POST /api/packets/import`.
+const MAX_ARCHIVE_BYTES: usize = 64 * 1024 * 1024;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // ── Logging ─────────────────────────────────────────────────────────
@@ -29,23 +43,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     info!("║   DIDComm v2.1 P2P Demo — Affinidi Messaging SDK   ║");
     info!("╚══════════════════════════════════════════════════════╝");
 
-    // ── Packet event channel ────────────────────────────────────────────
-    let packet_tx = packet_logger::create_packet_channel();
-
-    // ── Initialise TDK + ATM + profiles ─────────────────────────────────
-    let environment_name =
-        env::var("TDK_ENVIRONMENT").unwrap_or_else(|_| "local".to_string());
-
-    let state = mediator::initialise(&environment_name, packet_tx).await?;
-
-    // ── Axum router ─────────────────────────────────────────────────────
-    let api_routes = Router::new()
-        .route("/identities", get(api::get_identities))
-        .route("/messages/send", post(api::send_message))
-        .route("/ping", post(api::send_ping))
-        .route("/messages/{alias}", get(api::fetch_messages))
-        .route("/packets/stream", get(api::packet_stream))
-        .route("/reset", post(api::reset_demo));
+    // ── API: live mediator, or offline replay of a recorded session ─────
+    let api_routes = match env::var("REPLAY_ARCHIVE") {
+        Ok(path) => replay_routes(&path)?,
+        Err(_) => live_routes().await?,
+    };
 
     let cors = CorsLayer::new()
         .allow_origin(Any)
@@ -56,8 +58,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .nest("/api", api_routes)
         // Serve the built React frontend from ./frontend/dist
         .fallback_service(ServeDir::new("frontend/dist").append_index_html_on_directories(true))
-        .layer(cors)
-        .with_state(state);
+        .layer(cors);
 
     // ── Start server ────────────────────────────────────────────────────
     let port: u16 = env::var("PORT")
@@ -75,3 +76,75 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     Ok(())
 }
+
+/// The full API, backed by the mediator configured in `TDK_ENVIRONMENT`.
+async fn live_routes() -> Result<Router, Box<dyn std::error::Error + Send + Sync>> {
+    // ── Session store + packet event log ────────────────────────────────
+    let store = match store::Store::open() {
+        Ok(store) => Some(Arc::new(store)),
+        Err(e) => {
+            warn!("Session store unavailable, running without persistence: {e}");
+            None
+        }
+    };
+    let packet_tx = packet_logger::create_packet_log(store.clone());
+
+    // ── Initialise TDK + ATM + profiles ─────────────────────────────────
+    let environment_name =
+        env::var("TDK_ENVIRONMENT").unwrap_or_else(|_| "local".to_string());
+
+    let state = mediator::initialise(&environment_name, packet_tx, store).await?;
+
+    // ── Axum router ─────────────────────────────────────────────────────
+    let api_routes = Router::new()
+        .route("/identities", get(api::get_identities).post(api::create_identity))
+        .route("/identities/{alias}", delete(api::remove_identity))
+        .route("/identities/{alias}/did-document", get(api::get_did_document))
+        .route("/identities/{alias}/rotate", post(api::rotate_did))
+        .route("/identities/{alias}/contacts", get(api::get_contacts))
+        .route(
+            "/identities/{alias}/listener",
+            get(api::get_listener).put(api::set_listener),
+        )
+        .route("/did-document", get(api::resolve_did_document))
+        .route("/messages/send", post(api::send_message))
+        .route("/ping", post(api::send_ping))
+        .route("/discover-features", post(api::discover_features))
+        .route("/oob/invitations", post(api::create_invitation))
+        .route("/oob/accept", post(api::accept_invitation))
+        .route("/messages/{alias}", get(api::fetch_messages).delete(api::delete_messages))
+        .route("/pickup/{alias}", post(api::pickup_messages))
+        .route("/threads/{id}", get(api::get_thread))
+        .route(
+            "/files/send",
+            post(api::send_file)
+                .layer(DefaultBodyLimit::max(flows::file_transfer::MAX_FILE_BYTES)),
+        )
+        .route("/files", get(api::get_transfers))
+        .route("/files/{alias}/{transfer_id}", get(api::download_file))
+        .route("/packets", get(api::get_packets::<mediator::AppState>))
+        .route("/packets/stream", get(api::packet_stream::<mediator::AppState>))
+        .route("/packets/export", get(api::export_packets))
+        .route(
+            "/packets/import",
+            post(api::import_packets).layer(DefaultBodyLimit::max(MAX_ARCHIVE_BYTES)),
+        )
+        .route("/reset", post(api::reset_demo))
+        .with_state(state);
+
+    Ok(api_routes)
+}
+
+/// Read-only API serving the archive at `path`, played back from the start.
+fn replay_routes(path: &str) -> Result<Router, Box<dyn std::error::Error + Send + Sync>> {
+    let state = Arc::new(replay::ReplayState::load(path)?);
+    state.restart();
+
+    Ok(Router::new()
+        .route("/identities", get(api::get_replay_identities))
+        .route("/packets", get(api::get_packets::<replay::ReplayState>))
+        .route("/packets/stream", get(api::packet_stream::<replay::ReplayState>))
+        .route("/reset", post(api::restart_replay))
+        .fallback(api::replay_unavailable)
+        .with_state(state))
+}
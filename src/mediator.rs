@@ -1,48 +1,504 @@
-/// Mediator module — initialises the TDK, ATM, and profiles for Alice & Bob.
+/// Mediator module — initialises the TDK, ATM, and every identity profile.
 ///
 /// Reads configuration from `environments.json` (produced by `setup_environment`)
-/// and sets up both identities with ACLs so they can exchange messages.
+/// and sets up all identities with ACLs so they can exchange messages.
+use serde::Serialize;
+use serde_json::Value;
+use sha256::digest;
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::sync::Arc;
-use tokio::sync::broadcast;
-use tracing::{error, info};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
 
 use affinidi_messaging_sdk::{
     ATM,
+    errors::ATMError,
     profiles::ATMProfile,
     protocols::mediator::acls::{AccessListModeType, MediatorACLSet},
 };
-use affinidi_tdk::{TDK, common::config::TDKConfig};
+use affinidi_messaging_didcomm::Message;
+use affinidi_tdk::{TDK, common::{config::TDKConfig, profiles::TDKProfile}};
 
-use crate::identity::IdentityInfo;
-use crate::packet_logger::PacketEvent;
+use crate::contacts::ContactBook;
+use crate::envelope;
+use crate::error::FlowError;
+use crate::flows::discover_features::DISCLOSED_PROTOCOLS;
+use crate::identity::{Identity, IdentityInfo, IdentityRegistry, generate_peer_profile};
+use crate::inbound::{self, BASIC_MESSAGE_TYPE, InboundRouter};
+use crate::packet_logger::PacketLog;
+use crate::store::{MessageDirection, Store};
+use crate::threads::ThreadStore;
+use crate::transfers::TransferStore;
 
 /// Shared application state passed into every Axum handler.
 pub struct AppState {
     pub atm: Arc<ATM>,
     pub tdk: Arc<TDK>,
 
-    // Activated ATM profiles (thread-safe handles)
-    pub alice_profile: Arc<ATMProfile>,
-    pub bob_profile: Arc<ATMProfile>,
+    // Active identities (ATM profile handles + public metadata), keyed by alias
+    pub identities: RwLock<IdentityRegistry>,
 
-    // Public identity metadata for the frontend
-    pub alice_info: IdentityInfo,
-    pub bob_info: IdentityInfo,
+    // Inbound listeners + message correlation bookkeeping
+    pub inbound: InboundRouter,
 
-    // Bob's mediator DID (needed for forwarding)
-    pub alice_mediator_did: String,
-    pub bob_mediator_did: String,
+    // What each identity knows about its peers (updated on DID rotation)
+    pub contacts: ContactBook,
 
-    // Packet event broadcast channel
-    pub packet_tx: broadcast::Sender<PacketEvent>,
+    // basicmessage conversations by thread
+    pub threads: ThreadStore,
+
+    // Chunked file transfers sent and received
+    pub transfers: TransferStore,
+
+    // Out-of-band invitations issued by our identities (invitation ID → inviter alias)
+    pub invitations: RwLock<HashMap<String, String>>,
+
+    // Aliases of identities being created (registry keys), so two requests
+    // can't activate profiles under the same alias at once
+    creating: std::sync::Mutex<HashSet<String>>,
+
+    // Answer expired inbound messages with a problem report (REPORT_EXPIRED_MESSAGES)
+    pub report_expired: bool,
+
+    // SQLite session store (None if the database could not be opened)
+    pub store: Option<Arc<Store>>,
+
+    // Packet event history + live broadcast channel
+    pub packet_tx: PacketLog,
+}
+
+impl AppState {
+    /// Look up an active identity by alias (case-insensitive).
+    pub async fn identity(&self, alias: &str) -> Option<Identity> {
+        self.identities.read().await.get(alias).cloned()
+    }
+
+    /// Lower-cased alias of the identity that owns `did`, if it is one of ours.
+    pub async fn alias_for_did(&self, did: &str) -> Option<String> {
+        self.identities
+            .read()
+            .await
+            .find_by_did(did)
+            .map(|i| i.alias().to_lowercase())
+    }
+
+    /// Structural decode of a packed JWE/JWS (see `envelope.rs`), naming our
+    /// identities and their mediator behind its key IDs.
+    pub async fn decode_envelope(&self, raw: &Value) -> Option<Value> {
+        let registry = self.identities.read().await;
+        envelope::decode(raw, |did| {
+            if let Some(identity) = registry.find_by_did(did) {
+                return Some(identity.alias().to_lowercase());
+            }
+            registry
+                .all()
+                .any(|i| i.mediator_did == did)
+                .then(|| "mediator".to_string())
+        })
+    }
+
+    /// Persist a plaintext message sent or received by one of our identities.
+    pub fn persist_message(
+        &self,
+        direction: MessageDirection,
+        msg: &Message,
+        from_alias: Option<&str>,
+        to_alias: Option<&str>,
+    ) {
+        if let Some(store) = &self.store {
+            store.record_message(direction, msg, from_alias, to_alias);
+        }
+    }
+
+    /// Activate a TDK profile: register it with the TDK and ATM (enabling its
+    /// WebSocket for live pickup) and fetch its mediator account.
+    ///
+    /// A DID the mediator doesn't know is an error unless `create_account` is
+    /// set — only freshly generated DIDs (runtime creation, rotation) get a new
+    /// account; configured identities must already be registered.
+    /// The returned identity is not yet in the registry.
+    pub async fn activate_profile(
+        &self,
+        tdk_profile: &TDKProfile,
+        create_account: bool,
+    ) -> Result<Identity, Box<dyn std::error::Error + Send + Sync>> {
+        let alias = &tdk_profile.alias;
+        self.tdk.add_profile(tdk_profile).await;
+
+        let profile = self
+            .atm
+            .profile_add(&ATMProfile::from_tdk_profile(&self.atm, tdk_profile).await?, true)
+            .await?;
+
+        let account = match self.atm.mediator().account_get(&profile, None).await? {
+            Some(account) => account,
+            None if !create_account => {
+                return Err(format!("{alias} account not found on mediator").into());
+            }
+            None => {
+                let did_hash = digest(&profile.inner.did);
+                info!("{alias} has no mediator account — registering {did_hash}");
+                self.atm
+                    .mediator()
+                    .account_add(&profile, &did_hash, None)
+                    .await?
+            }
+        };
+        info!("{alias} profile active — DID hash: {}", account.did_hash);
+
+        let acl_mode = MediatorACLSet::from_u64(account.acls)
+            .get_access_list_mode()
+            .0;
+
+        let mediator_did = tdk_profile.mediator.clone().unwrap_or_default();
+        let resolved = self.tdk.did_resolver().resolve(&profile.inner.did).await?;
+        let info = IdentityInfo::from_document(
+            alias,
+            &profile.inner.did,
+            Some(&mediator_did),
+            &resolved.doc,
+        );
+
+        Ok(Identity {
+            profile,
+            info,
+            mediator_did,
+            did_hash: account.did_hash,
+            acl_mode,
+            runtime: false,
+            from_prior: None,
+        })
+    }
+
+    /// Allow `identity` and every identity in `peers` to message each other.
+    pub async fn grant_mutual_access(
+        &self,
+        identity: &Identity,
+        peers: &[Identity],
+    ) -> Result<(), ATMError> {
+        let peer_refs: Vec<&Identity> = peers.iter().collect();
+        self.grant_access(identity, &peer_refs).await?;
+        for peer in peers {
+            self.grant_access(peer, &[identity]).await?;
+        }
+        Ok(())
+    }
+
+    /// Allow every identity in `peers` to message `identity`, if its account
+    /// runs in explicit-allow mode.
+    pub async fn grant_access(
+        &self,
+        identity: &Identity,
+        peers: &[&Identity],
+    ) -> Result<(), ATMError> {
+        let hashes: Vec<&str> = peers.iter().map(|p| p.did_hash.as_str()).collect();
+        self.grant_access_hashes(identity, &hashes).await
+    }
+
+    /// Allow the DIDs behind `did_hashes` (SHA-256 of each DID) to message
+    /// `identity`, if its account runs in explicit-allow mode.
+    pub async fn grant_access_hashes(
+        &self,
+        identity: &Identity,
+        did_hashes: &[&str],
+    ) -> Result<(), ATMError> {
+        if identity.acl_mode != AccessListModeType::ExplicitAllow {
+            return Ok(());
+        }
+
+        let hashes: Vec<&str> = did_hashes
+            .iter()
+            .copied()
+            .filter(|h| *h != identity.did_hash)
+            .collect();
+        if hashes.is_empty() {
+            return Ok(());
+        }
+
+        self.atm
+            .mediator()
+            .access_list_add(&identity.profile, None, &hashes)
+            .await?;
+        info!("Added {} peer(s) to {}'s allow list", hashes.len(), identity.alias());
+        Ok(())
+    }
+
+    /// Generate a fresh did:peer identity at runtime, register its mediator
+    /// account, wire up ACLs with every existing identity, add it to the
+    /// registry and start its inbound listener.
+    ///
+    /// `mediator_did` defaults to the environment's default mediator, falling
+    /// back to the mediator used by the existing identities. The alias is
+    /// reserved until the identity is registered or creation fails.
+    pub async fn create_identity(
+        self: &Arc<Self>,
+        alias: &str,
+        mediator_did: Option<&str>,
+    ) -> Result<Identity, FlowError> {
+        let key = IdentityRegistry::key(alias);
+        {
+            let registry = self.identities.read().await;
+            if registry.contains(alias) || !self.creating.lock().unwrap().insert(key.clone()) {
+                return Err(FlowError::AliasExists(alias.to_string()));
+            }
+        }
+        let result = self.create_reserved_identity(alias, mediator_did).await;
+        self.creating.lock().unwrap().remove(&key);
+        result
+    }
+
+    /// `create_identity` once `alias` is reserved. Anything activated on the
+    /// mediator is undone if a later step fails.
+    async fn create_reserved_identity(
+        self: &Arc<Self>,
+        alias: &str,
+        mediator_did: Option<&str>,
+    ) -> Result<Identity, FlowError> {
+        let mediator_did = match mediator_did {
+            Some(did) => did.to_string(),
+            None => self
+                .default_mediator_did()
+                .await
+                .ok_or_else(|| FlowError::Internal("No mediator DID available".into()))?,
+        };
+
+        // ── 1. Generate did:peer + secrets ──────────────────────────────────
+        let tdk_profile = generate_peer_profile(alias, &mediator_did)
+            .map_err(|e| FlowError::Internal(format!("did:peer generation failed: {e}")))?;
+        info!("Generated {alias} DID: {}", tdk_profile.did);
+
+        // ── 2. Activate profile (account registration + WebSocket) ──────────
+        let mut identity = self
+            .activate_profile(&tdk_profile, true)
+            .await
+            .map_err(|e| FlowError::Internal(format!("Profile activation failed: {e}")))?;
+        identity.runtime = true;
+
+        // ── 3. Set up ACLs in both directions ───────────────────────────────
+        let peers: Vec<Identity> = self.identities.read().await.all().cloned().collect();
+        if let Err(e) = self.grant_mutual_access(&identity, &peers).await {
+            self.discard_identity(&identity, &peers).await;
+            return Err(FlowError::from_atm("access_list_add", e));
+        }
+
+        // ── 4. Register ─────────────────────────────────────────────────────
+        let mut registry = self.identities.write().await;
+        if registry.contains(alias) {
+            drop(registry);
+            self.discard_identity(&identity, &peers).await;
+            return Err(FlowError::AliasExists(alias.to_string()));
+        }
+        registry.insert(identity.clone());
+        drop(registry);
+
+        inbound::spawn_listener(self, &identity);
+        Ok(identity)
+    }
+
+    /// Swap `old` for a freshly generated did:peer under the same alias: the
+    /// old profile is closed, the new one activated and allowed to message
+    /// every peer (and vice versa), and `from_prior` is kept on the new
+    /// identity so its next messages announce the rotation.
+    ///
+    /// The old DID is then retired: it is removed from every peer's access
+    /// list and, for runtime identities, its mediator account is deleted.
+    /// Identities from `environments.json` keep their old account, since the
+    /// configured DID is used again on the next start.
+    ///
+    /// If the new profile can't be activated or granted access, it is undone
+    /// and the old one restored.
+    pub async fn rotate_identity(
+        self: &Arc<Self>,
+        old: &Identity,
+        tdk_profile: &TDKProfile,
+        from_prior: String,
+    ) -> Result<Identity, FlowError> {
+        let alias = old.alias().to_string();
+        self.inbound.stop_listener(&alias);
+        if let Err(e) = self.atm.profile_remove(&old.profile.inner.alias).await {
+            warn!("Failed to close {alias}'s old profile: {e}");
+        }
+
+        let mut identity = match self.activate_profile(tdk_profile, true).await {
+            Ok(identity) => identity,
+            Err(e) => {
+                self.restore_profile(old).await;
+                return Err(FlowError::Internal(format!(
+                    "Profile activation failed: {e}"
+                )));
+            }
+        };
+        identity.runtime = old.runtime;
+        identity.from_prior = Some(from_prior);
+
+        let peers: Vec<Identity> = self
+            .identities
+            .read()
+            .await
+            .all()
+            .filter(|p| p.did() != old.did())
+            .cloned()
+            .collect();
+        if let Err(e) = self.grant_mutual_access(&identity, &peers).await {
+            // Undo any grants and the new account before the old profile returns
+            self.discard_identity(&identity, &peers).await;
+            self.restore_profile(old).await;
+            return Err(FlowError::from_atm("access_list_add", e));
+        }
+
+        self.identities.write().await.insert(identity.clone());
+        inbound::spawn_listener(self, &identity);
+        info!("{alias} rotated {} → {}", old.did(), identity.did());
+
+        let (acl_removed_from, account_deleted) = self.retire_did(old, &peers, old.runtime).await;
+        info!(
+            "{alias}'s old DID retired (removed from {} allow list(s), account deleted: {account_deleted})",
+            acl_removed_from.len()
+        );
+        Ok(identity)
+    }
+
+    /// Undo a freshly activated identity that never made it into the registry:
+    /// revoke its grants, delete its new mediator account and close its profile.
+    async fn discard_identity(&self, identity: &Identity, peers: &[Identity]) {
+        self.retire_did(identity, peers, true).await;
+        if let Err(e) = self.atm.profile_remove(&identity.profile.inner.alias).await {
+            warn!("Failed to close {}'s new profile: {e}", identity.alias());
+        }
+    }
+
+    /// Put `old` back after a failed rotation: re-add its profile and restart
+    /// its listener.
+    async fn restore_profile(self: &Arc<Self>, old: &Identity) {
+        if let Err(e) = self.atm.profile_add(&old.profile, true).await {
+            warn!("Failed to restore {}'s old profile: {e}", old.alias());
+        }
+        inbound::spawn_listener(self, old);
+    }
+
+    /// Deactivate an identity: stop its inbound listener, optionally delete its
+    /// mediator account, remove it from every peer's access list, close its
+    /// WebSocket and drop it from the ATM and the registry.
+    ///
+    /// Cleanup after the registry removal is best-effort — failures are logged
+    /// and reflected in the returned summary rather than aborting.
+    pub async fn remove_identity(
+        &self,
+        alias: &str,
+        delete_account: bool,
+    ) -> Option<IdentityRemoval> {
+        let identity = self.identities.write().await.remove(alias)?;
+        let alias = identity.alias().to_string();
+        self.inbound.stop_listener(&alias);
+        self.inbound.clear_pause(&alias);
+        self.contacts.remove_owner(&alias);
+
+        // ── 1. Remove from peers' access lists, delete the mediator account ─
+        let peers: Vec<Identity> = self.identities.read().await.all().cloned().collect();
+        let (acl_removed_from, account_deleted) =
+            self.retire_did(&identity, &peers, delete_account).await;
+
+        // ── 2. Close WebSocket + remove from ATM ────────────────────────────
+        if let Err(e) = self.atm.profile_remove(&identity.profile.inner.alias).await {
+            warn!("Failed to remove {alias} from ATM: {e}");
+        }
+        info!("{alias} deactivated (account deleted: {account_deleted})");
+
+        Some(IdentityRemoval {
+            alias,
+            did: identity.did().to_string(),
+            acl_removed_from,
+            account_deleted,
+        })
+    }
+
+    /// Take `identity`'s DID out of service: remove it from the allow list of
+    /// every explicit-allow peer and, if `delete_account`, delete its mediator
+    /// account (the profile's secrets must still be known to the TDK).
+    ///
+    /// Best-effort — failures are logged. Returns the peers whose allow list
+    /// was updated and whether the account was deleted.
+    async fn retire_did(
+        &self,
+        identity: &Identity,
+        peers: &[Identity],
+        delete_account: bool,
+    ) -> (Vec<String>, bool) {
+        let alias = identity.alias();
+        let mut acl_removed_from = Vec::new();
+        for peer in peers
+            .iter()
+            .filter(|p| p.acl_mode == AccessListModeType::ExplicitAllow)
+        {
+            match self
+                .atm
+                .mediator()
+                .access_list_remove(&peer.profile, None, &[&identity.did_hash])
+                .await
+            {
+                Ok(_) => acl_removed_from.push(peer.alias().to_string()),
+                Err(e) => warn!(
+                    "Failed to remove {alias} from {}'s allow list: {e}",
+                    peer.alias()
+                ),
+            }
+        }
+
+        let account_deleted = if delete_account {
+            match self
+                .atm
+                .mediator()
+                .account_remove(&identity.profile, None)
+                .await
+            {
+                Ok(removed) => removed,
+                Err(e) => {
+                    warn!(
+                        "Failed to delete mediator account for {}: {e}",
+                        identity.did()
+                    );
+                    false
+                }
+            }
+        } else {
+            false
+        };
+        (acl_removed_from, account_deleted)
+    }
+
+    /// Mediator DID for newly created identities.
+    async fn default_mediator_did(&self) -> Option<String> {
+        if let Some(did) = &self.tdk.get_shared_state().environment.default_mediator {
+            return Some(did.clone());
+        }
+        self.identities
+            .read()
+            .await
+            .all()
+            .map(|i| i.mediator_did.clone())
+            .find(|m| !m.is_empty())
+    }
+}
+
+/// Summary of an identity removal.
+#[derive(Debug, Serialize)]
+pub struct IdentityRemoval {
+    pub alias: String,
+    pub did: String,
+    /// Peers whose allow list no longer contains the removed DID.
+    pub acl_removed_from: Vec<String>,
+    pub account_deleted: bool,
 }
 
 /// Bootstrap everything: TDK → ATM → profiles → ACLs.
 ///
 /// `environment_name` corresponds to the key inside `environments.json`.
+/// Every profile in that environment is activated.
 pub async fn initialise(
     environment_name: &str,
-    packet_tx: broadcast::Sender<PacketEvent>,
+    packet_tx: PacketLog,
+    store: Option<Arc<Store>>,
 ) -> Result<Arc<AppState>, Box<dyn std::error::Error + Send + Sync>> {
     info!("Initialising TDK with environment '{environment_name}'");
 
@@ -55,112 +511,94 @@ pub async fn initialise(
     )
     .await?;
 
-    let environment = &tdk.get_shared_state().environment;
+    let environment = tdk.get_shared_state().environment.clone();
     let atm = tdk.atm.clone().unwrap();
 
-    // ── 2. Activate Alice profile ───────────────────────────────────────
-    let tdk_alice = environment
-        .profiles
-        .get("Alice")
-        .ok_or_else(|| {
-            format!("Alice not found in environment '{environment_name}'")
-        })?;
-    tdk.add_profile(tdk_alice).await;
-
-    let atm_alice = atm
-        .profile_add(&ATMProfile::from_tdk_profile(&atm, tdk_alice).await?, true)
-        .await?;
-
-    let alice_account = atm
-        .mediator()
-        .account_get(&atm_alice, None)
-        .await?
-        .ok_or("Alice account not found on mediator")?;
-    info!("Alice profile active — DID hash: {}", alice_account.did_hash);
-
-    let alice_acl_mode = MediatorACLSet::from_u64(alice_account.acls)
-        .get_access_list_mode()
-        .0;
-
-    // ── 3. Activate Bob profile ─────────────────────────────────────────
-    let tdk_bob = environment
-        .profiles
-        .get("Bob")
-        .ok_or_else(|| {
-            format!("Bob not found in environment '{environment_name}'")
-        })?;
-    tdk.add_profile(tdk_bob).await;
-
-    let atm_bob = atm
-        .profile_add(&ATMProfile::from_tdk_profile(&atm, tdk_bob).await?, true)
-        .await?;
-
-    let bob_account = atm
-        .mediator()
-        .account_get(&atm_bob, None)
-        .await?
-        .ok_or("Bob account not found on mediator")?;
-    info!("Bob profile active — DID hash: {}", bob_account.did_hash);
-
-    let bob_acl_mode = MediatorACLSet::from_u64(bob_account.acls)
-        .get_access_list_mode()
-        .0;
-
-    // ── 4. Set up ACLs ──────────────────────────────────────────────────
-    if let AccessListModeType::ExplicitAllow = alice_acl_mode {
-        atm.mediator()
-            .access_list_add(&atm_alice, None, &[&bob_account.did_hash])
-            .await?;
-        info!("Added Bob to Alice's allow list");
+    if environment.profiles.is_empty() {
+        return Err(format!("No profiles found in environment '{environment_name}'").into());
     }
 
-    if let AccessListModeType::ExplicitAllow = bob_acl_mode {
-        atm.mediator()
-            .access_list_add(&atm_bob, None, &[&alice_account.did_hash])
-            .await?;
-        info!("Added Alice to Bob's allow list");
+    let state = AppState {
+        atm: Arc::new(atm),
+        tdk: Arc::new(tdk),
+        identities: RwLock::new(IdentityRegistry::default()),
+        inbound: InboundRouter::default(),
+        contacts: ContactBook::default(),
+        threads: ThreadStore::default(),
+        transfers: TransferStore::default(),
+        invitations: RwLock::new(HashMap::new()),
+        creating: std::sync::Mutex::new(HashSet::new()),
+        report_expired: env::var("REPORT_EXPIRED_MESSAGES")
+            .map(|v| !matches!(v.to_lowercase().as_str(), "false" | "0" | "no"))
+            .unwrap_or(true),
+        store,
+        packet_tx,
+    };
+
+    // Rebuild the conversation threads recorded by a previous run
+    if let Some(store) = &state.store {
+        let messages = store.messages_of_type(BASIC_MESSAGE_TYPE);
+        for stored in &messages {
+            state.threads.record(
+                &stored.message,
+                stored.from_alias.as_deref(),
+                stored.to_alias.as_deref(),
+                stored.direction == MessageDirection::Received,
+            );
+        }
+        if !messages.is_empty() {
+            info!(
+                "Restored {} thread messages from the session store",
+                messages.len()
+            );
+        }
     }
 
-    // ── 5. Enable WebSocket streams for live pickup ─────────────────────
-    if let Err(e) = atm.profile_enable_websocket(&atm_alice).await {
-        error!("Failed to enable WS for Alice: {e}");
-    } else {
-        info!("WebSocket enabled for Alice");
+    // ── 2. Advertise the protocols we answer (Discover Features) ────────
+    state
+        .atm
+        .discover_features()
+        .get_discoverable_state()
+        .write()
+        .await
+        .protocols
+        .extend(DISCLOSED_PROTOCOLS.iter().map(|p| p.to_string()));
+
+    // ── 3. Activate every profile ───────────────────────────────────────
+    let mut tdk_profiles: Vec<&TDKProfile> = environment.profiles.values().collect();
+    tdk_profiles.sort_by(|a, b| a.alias.cmp(&b.alias));
+
+    // Configured aliases must not shadow the names flows use for other parties
+    for tdk_profile in &tdk_profiles {
+        IdentityRegistry::validate_alias(&tdk_profile.alias)
+            .map_err(|e| format!("environments.json profile: {e}"))?;
     }
 
-    if let Err(e) = atm.profile_enable_websocket(&atm_bob).await {
-        error!("Failed to enable WS for Bob: {e}");
-    } else {
-        info!("WebSocket enabled for Bob");
+    let mut identities = Vec::with_capacity(tdk_profiles.len());
+    for tdk_profile in tdk_profiles {
+        identities.push(state.activate_profile(tdk_profile, false).await?);
     }
 
-    // ── 6. Build identity metadata ──────────────────────────────────────
-    let alice_mediator_did = tdk_alice.mediator.clone().unwrap_or_default();
-    let bob_mediator_did = tdk_bob.mediator.clone().unwrap_or_default();
+    // ── 4. Set up ACLs ──────────────────────────────────────────────────
+    let all: Vec<&Identity> = identities.iter().collect();
+    for identity in &identities {
+        state.grant_access(identity, &all).await?;
+    }
 
-    let alice_identity = IdentityInfo::from_profile(
-        "Alice",
-        &atm_alice.inner.did,
-        Some(&alice_mediator_did),
-    );
-    let bob_identity = IdentityInfo::from_profile(
-        "Bob",
-        &atm_bob.inner.did,
-        Some(&bob_mediator_did),
-    );
+    // ── 5. Register identity metadata ───────────────────────────────────
+    {
+        let mut registry = state.identities.write().await;
+        for identity in &identities {
+            info!("{:<6} DID: {}", identity.alias(), identity.did());
+            registry.insert(identity.clone());
+        }
+    }
 
-    info!("Alice DID: {}", alice_identity.did);
-    info!("Bob   DID: {}", bob_identity.did);
+    // ── 6. Start inbound listeners ──────────────────────────────────────
+    let state = Arc::new(state);
+    for identity in &identities {
+        inbound::spawn_listener(&state, identity);
+    }
 
-    Ok(Arc::new(AppState {
-        atm: Arc::new(atm),
-        tdk: Arc::new(tdk),
-        alice_profile: atm_alice,
-        bob_profile: atm_bob,
-        alice_info: alice_identity,
-        bob_info: bob_identity,
-        alice_mediator_did,
-        bob_mediator_did,
-        packet_tx,
-    }))
+    Ok(state)
 }
@@ -1,9 +1,16 @@
-/// Packet logger — captures every DIDComm pack/unpack event and fans it out
-/// to connected frontend clients via SSE.
-use chrono::Utc;
+/// Packet logger — captures every DIDComm pack/unpack event, keeps a bounded
+/// history of them and fans them out to connected frontend clients via SSE.
+use std::collections::{HashSet, VecDeque};
+use std::env;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::store::Store;
 
 /// The step within the DIDComm send/receive pipeline.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -17,8 +24,16 @@ pub enum PacketStep {
     MediatorAck,
     TrustPing,
     TrustPong,
+    FeaturesQuery,
+    FeaturesDisclose,
+    ProblemReport,
     MessagePickup,
     MessageDelivery,
+    MessageDelete,
+    OobInvitation,
+    DidRotation,
+    MessageExpired,
+    FileTransfer,
 }
 
 impl PacketStep {
@@ -33,8 +48,16 @@ impl PacketStep {
             Self::MediatorAck => "⑤ Mediator ACK",
             Self::TrustPing => "① Trust Ping",
             Self::TrustPong => "② Trust Pong",
+            Self::FeaturesQuery => "① Features Query",
+            Self::FeaturesDisclose => "② Features Disclose",
+            Self::ProblemReport => "⚠ Problem Report",
             Self::MessagePickup => "⑥ Message Pickup",
             Self::MessageDelivery => "⑥ Message Delivery",
+            Self::MessageDelete => "✕ Message Delete",
+            Self::OobInvitation => "⓪ OOB Invitation",
+            Self::DidRotation => "↻ DID Rotation",
+            Self::MessageExpired => "⌛ Message Expired",
+            Self::FileTransfer => "⇪ File Transfer",
         }
     }
 
@@ -47,7 +70,14 @@ impl PacketStep {
             Self::MediatorSend => "orange",
             Self::MediatorAck => "green",
             Self::TrustPing | Self::TrustPong => "purple",
+            Self::FeaturesQuery | Self::FeaturesDisclose => "cyan",
+            Self::ProblemReport => "pink",
             Self::MessagePickup | Self::MessageDelivery => "green",
+            Self::MessageDelete => "gray",
+            Self::OobInvitation => "indigo",
+            Self::DidRotation => "amber",
+            Self::MessageExpired => "rose",
+            Self::FileTransfer => "lime",
         }
     }
 }
@@ -80,6 +110,9 @@ pub struct PacketEvent {
     pub raw_json: Value,
     /// Optional correlation ID linking related events together.
     pub correlation_id: Option<String>,
+    /// Explanatory notes for the inspector (protection mode, key IDs, what each party can see).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Value>,
 }
 
 impl PacketEvent {
@@ -106,20 +139,489 @@ impl PacketEvent {
             color,
             raw_json,
             correlation_id,
+            annotations: None,
         }
     }
 
+    /// The "reset" event that tells the frontend to clear its state.
+    pub fn reset() -> Self {
+        Self::new(
+            PacketDirection::Outbound,
+            "system",
+            "all",
+            PacketStep::PlaintextMessage,
+            serde_json::json!({ "action": "reset" }),
+            None,
+        )
+    }
+
+    /// Whether this is the "reset" control event rather than a packet.
+    pub fn is_reset(&self) -> bool {
+        self.from == "system" && self.raw_json["action"] == "reset"
+    }
+
     /// Set human-readable aliases for from/to.
     pub fn with_aliases(mut self, from_alias: &str, to_alias: &str) -> Self {
         self.from_alias = Some(from_alias.to_string());
         self.to_alias = Some(to_alias.to_string());
         self
     }
+
+    /// Attach inspector annotations.
+    pub fn with_annotations(mut self, annotations: Value) -> Self {
+        self.annotations = Some(annotations);
+        self
+    }
+}
+
+/// Number of events kept in the packet history when `PACKET_HISTORY_SIZE` is unset.
+const DEFAULT_HISTORY_SIZE: usize = 2000;
+
+/// Capacity of the live broadcast channel; subscribers that fall further behind
+/// catch up from the history.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// A packet event with its position in the log.
+#[derive(Debug, Clone)]
+pub struct LoggedPacket {
+    pub seq: u64,
+    pub event: PacketEvent,
+}
+
+/// Where a new subscriber starts.
+#[derive(Debug)]
+pub enum Replay {
+    /// Live events only.
+    None,
+    /// The whole retained history, then live events.
+    All,
+    /// Events after the one with this `PacketEvent::id` (everything retained
+    /// if it has been evicted).
+    After(String),
+}
+
+/// Filters for `GET /api/packets`.
+#[derive(Debug, Default, Deserialize)]
+pub struct PacketFilter {
+    pub correlation_id: Option<String>,
+    pub step: Option<PacketStep>,
+    /// Matches either the `from_alias` or the `to_alias` (case-insensitive).
+    pub alias: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Return only the newest `limit` matches.
+    pub limit: Option<usize>,
+}
+
+impl PacketFilter {
+    fn matches(&self, event: &PacketEvent) -> bool {
+        if self
+            .correlation_id
+            .as_ref()
+            .is_some_and(|c| event.correlation_id.as_ref() != Some(c))
+        {
+            return false;
+        }
+        if self.step.as_ref().is_some_and(|s| *s != event.step) {
+            return false;
+        }
+        if let Some(alias) = &self.alias {
+            let matches =
+                |a: &Option<String>| a.as_ref().is_some_and(|a| a.eq_ignore_ascii_case(alias));
+            if !matches(&event.from_alias) && !matches(&event.to_alias) {
+                return false;
+            }
+        }
+        if self.since.is_some() || self.until.is_some() {
+            let Ok(timestamp) = DateTime::parse_from_rfc3339(&event.timestamp) else {
+                return false;
+            };
+            if self.since.is_some_and(|since| timestamp < since)
+                || self.until.is_some_and(|until| timestamp > until)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+struct History {
+    events: VecDeque<LoggedPacket>,
+    next_seq: u64,
+}
+
+/// Every packet event: kept in a bounded in-memory history, broadcast to live
+/// subscribers and, when the session store is open, persisted to SQLite.
+pub struct PacketLog {
+    tx: broadcast::Sender<LoggedPacket>,
+    history: Mutex<History>,
+    capacity: usize,
+    store: Option<Arc<Store>>,
+}
+
+impl PacketLog {
+    /// Record `event` and broadcast it, returning the number of live subscribers.
+    pub fn send(&self, event: PacketEvent) -> usize {
+        if let Some(store) = &self.store {
+            store.record_packet(&event);
+        }
+        let mut history = self.history.lock().unwrap();
+        self.broadcast(&mut history, event, true)
+    }
+
+    /// Broadcast a control event (the reset marker) to live subscribers only.
+    /// It is neither persisted nor kept in the history, so it never shows up
+    /// in a replay or an export.
+    pub fn notify(&self, event: PacketEvent) -> usize {
+        let mut history = self.history.lock().unwrap();
+        self.broadcast(&mut history, event, false)
+    }
+
+    fn broadcast(&self, history: &mut History, event: PacketEvent, retain: bool) -> usize {
+        let packet = LoggedPacket {
+            seq: history.next_seq,
+            event,
+        };
+        history.next_seq += 1;
+        if retain {
+            history.events.push_back(packet.clone());
+            while history.events.len() > self.capacity {
+                history.events.pop_front();
+            }
+        }
+        // Broadcast under the lock so `subscribe` sees each event exactly once
+        self.tx.send(packet).unwrap_or(0)
+    }
+
+    /// Subscribe to live events, returning the history to replay first.
+    pub fn subscribe(
+        &self,
+        replay: Replay,
+    ) -> (Vec<LoggedPacket>, broadcast::Receiver<LoggedPacket>) {
+        let history = self.history.lock().unwrap();
+        let start = match &replay {
+            Replay::None => history.events.len(),
+            Replay::All => 0,
+            Replay::After(id) => history
+                .events
+                .iter()
+                .position(|p| p.event.id == *id)
+                .map_or(0, |i| i + 1),
+        };
+        let backlog = history.events.range(start..).cloned().collect();
+        (backlog, self.tx.subscribe())
+    }
+
+    /// Add recorded events to the history and the store and broadcast them,
+    /// skipping any whose ID is already retained. Returns how many were added.
+    pub fn import(&self, events: Vec<PacketEvent>) -> usize {
+        let mut history = self.history.lock().unwrap();
+        let mut known: HashSet<String> =
+            history.events.iter().map(|p| p.event.id.clone()).collect();
+        let mut added = 0;
+        for event in events {
+            if !known.insert(event.id.clone()) {
+                continue;
+            }
+            if let Some(store) = &self.store {
+                store.record_packet(&event);
+            }
+            self.broadcast(&mut history, event, true);
+            added += 1;
+        }
+        added
+    }
+
+    /// Retained events after `seq` (all of them if `None`).
+    pub fn after(&self, seq: Option<u64>) -> Vec<LoggedPacket> {
+        let history = self.history.lock().unwrap();
+        history
+            .events
+            .iter()
+            .filter(|p| seq.is_none_or(|seq| p.seq > seq))
+            .cloned()
+            .collect()
+    }
+
+    /// Retained events matching `filter`, oldest first.
+    pub fn query(&self, filter: &PacketFilter) -> Vec<PacketEvent> {
+        let history = self.history.lock().unwrap();
+        let mut events: Vec<PacketEvent> = history
+            .events
+            .iter()
+            .filter(|p| filter.matches(&p.event))
+            .map(|p| p.event.clone())
+            .collect();
+        if let Some(limit) = filter.limit {
+            events.drain(..events.len().saturating_sub(limit));
+        }
+        events
+    }
+
+    /// Forget every event held in memory (the store is truncated separately).
+    pub fn clear(&self) {
+        self.history.lock().unwrap().events.clear();
+    }
+}
+
+/// Create the packet log, sized by `PACKET_HISTORY_SIZE` and preloaded with
+/// the newest events persisted in `store`.
+pub fn create_packet_log(store: Option<Arc<Store>>) -> PacketLog {
+    let capacity = env::var("PACKET_HISTORY_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_HISTORY_SIZE);
+    create_packet_log_with_capacity(store, capacity)
 }
 
-/// Create a broadcast channel for packet events.
-/// Returns (sender, _receiver). The receiver is dropped — subscribers use `sender.subscribe()`.
-pub fn create_packet_channel() -> broadcast::Sender<PacketEvent> {
-    let (tx, _rx) = broadcast::channel::<PacketEvent>(256);
-    tx
+/// Like `create_packet_log`, but retaining `capacity` events regardless of
+/// `PACKET_HISTORY_SIZE`.
+pub fn create_packet_log_with_capacity(store: Option<Arc<Store>>, capacity: usize) -> PacketLog {
+    let capacity = capacity.max(1);
+    let events: VecDeque<LoggedPacket> = store
+        .as_ref()
+        .map(|store| store.recent_packets(capacity))
+        .unwrap_or_default()
+        .into_iter()
+        .enumerate()
+        .map(|(seq, event)| LoggedPacket {
+            seq: seq as u64,
+            event,
+        })
+        .collect();
+    if !events.is_empty() {
+        info!(
+            "Restored {} packet events from the session store",
+            events.len()
+        );
+    }
+    let (tx, _rx) = broadcast::channel::<LoggedPacket>(CHANNEL_CAPACITY);
+    PacketLog {
+        tx,
+        history: Mutex::new(History {
+            next_seq: events.len() as u64,
+            events,
+        }),
+        capacity,
+        store,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn log(capacity: usize) -> PacketLog {
+        let (tx, _rx) = broadcast::channel(CHANNEL_CAPACITY);
+        PacketLog {
+            tx,
+            history: Mutex::new(History {
+                events: VecDeque::new(),
+                next_seq: 0,
+            }),
+            capacity,
+            store: None,
+        }
+    }
+
+    fn event(step: PacketStep, correlation_id: &str, timestamp: &str) -> PacketEvent {
+        let mut event = PacketEvent::new(
+            PacketDirection::Outbound,
+            "did:peer:2.alice",
+            "did:peer:2.bob",
+            step,
+            json!({}),
+            Some(correlation_id.to_string()),
+        )
+        .with_aliases("alice", "bob");
+        event.timestamp = timestamp.to_string();
+        event
+    }
+
+    fn ids(packets: &[LoggedPacket]) -> Vec<&str> {
+        packets.iter().map(|p| p.event.id.as_str()).collect()
+    }
+
+    #[test]
+    fn filter_matches_each_field() {
+        let ping = event(PacketStep::TrustPing, "c1", "2026-01-01T10:00:00Z");
+
+        assert!(PacketFilter::default().matches(&ping));
+        let filter = |f: PacketFilter| f.matches(&ping);
+        assert!(filter(PacketFilter {
+            correlation_id: Some("c1".into()),
+            ..Default::default()
+        }));
+        assert!(!filter(PacketFilter {
+            correlation_id: Some("c2".into()),
+            ..Default::default()
+        }));
+        assert!(filter(PacketFilter {
+            step: Some(PacketStep::TrustPing),
+            ..Default::default()
+        }));
+        assert!(!filter(PacketFilter {
+            step: Some(PacketStep::TrustPong),
+            ..Default::default()
+        }));
+        for alias in ["Alice", "BOB"] {
+            assert!(filter(PacketFilter {
+                alias: Some(alias.into()),
+                ..Default::default()
+            }));
+        }
+        assert!(!filter(PacketFilter {
+            alias: Some("carol".into()),
+            ..Default::default()
+        }));
+
+        let at = |t: &str| Some(t.parse::<DateTime<Utc>>().unwrap());
+        assert!(filter(PacketFilter {
+            since: at("2026-01-01T10:00:00Z"),
+            until: at("2026-01-01T10:00:00Z"),
+            ..Default::default()
+        }));
+        assert!(!filter(PacketFilter {
+            since: at("2026-01-01T10:00:01Z"),
+            ..Default::default()
+        }));
+        assert!(!filter(PacketFilter {
+            until: at("2026-01-01T09:59:59Z"),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn time_filters_skip_unparseable_timestamps() {
+        let event = event(PacketStep::TrustPing, "c1", "yesterday");
+        assert!(
+            !PacketFilter {
+                since: Some(DateTime::UNIX_EPOCH),
+                ..Default::default()
+            }
+            .matches(&event)
+        );
+    }
+
+    #[test]
+    fn query_returns_the_newest_matches() {
+        let log = log(10);
+        for n in 0..4 {
+            log.send(event(
+                PacketStep::TrustPing,
+                &format!("c{n}"),
+                "2026-01-01T10:00:00Z",
+            ));
+        }
+        log.send(event(PacketStep::TrustPong, "c4", "2026-01-01T10:00:00Z"));
+
+        let pings = log.query(&PacketFilter {
+            step: Some(PacketStep::TrustPing),
+            limit: Some(2),
+            ..Default::default()
+        });
+        let correlations: Vec<_> = pings.iter().map(|e| e.correlation_id.as_deref()).collect();
+        assert_eq!(correlations, [Some("c2"), Some("c3")]);
+    }
+
+    #[test]
+    fn history_is_bounded_and_after_skips_seen_events() {
+        let log = log(3);
+        for n in 0..5 {
+            log.send(event(
+                PacketStep::TrustPing,
+                &format!("c{n}"),
+                "2026-01-01T10:00:00Z",
+            ));
+        }
+
+        let retained = log.after(None);
+        let seqs: Vec<u64> = retained.iter().map(|p| p.seq).collect();
+        assert_eq!(seqs, [2, 3, 4]);
+        assert_eq!(ids(&log.after(Some(3))), ids(&retained[2..]));
+        assert!(log.after(Some(4)).is_empty());
+        // A cursor older than the history gets everything still retained
+        assert_eq!(log.after(Some(0)).len(), 3);
+    }
+
+    #[test]
+    fn subscribe_replays_from_the_requested_point() {
+        let log = log(10);
+        for n in 0..3 {
+            log.send(event(
+                PacketStep::TrustPing,
+                &format!("c{n}"),
+                "2026-01-01T10:00:00Z",
+            ));
+        }
+        let all = log.after(None);
+
+        assert!(log.subscribe(Replay::None).0.is_empty());
+        assert_eq!(ids(&log.subscribe(Replay::All).0), ids(&all));
+        let (backlog, _) = log.subscribe(Replay::After(all[0].event.id.clone()));
+        assert_eq!(ids(&backlog), ids(&all[1..]));
+        let (backlog, _) = log.subscribe(Replay::After(all[2].event.id.clone()));
+        assert!(backlog.is_empty());
+    }
+
+    #[test]
+    fn unknown_last_event_id_replays_everything_retained() {
+        let log = log(2);
+        let first = event(PacketStep::TrustPing, "c0", "2026-01-01T10:00:00Z");
+        let evicted = first.id.clone();
+        log.send(first);
+        for n in 1..3 {
+            log.send(event(
+                PacketStep::TrustPing,
+                &format!("c{n}"),
+                "2026-01-01T10:00:00Z",
+            ));
+        }
+
+        let retained = log.after(None);
+        for id in [evicted, "not-an-event".to_string()] {
+            let (backlog, _) = log.subscribe(Replay::After(id));
+            assert_eq!(ids(&backlog), ids(&retained));
+        }
+    }
+
+    #[test]
+    fn subscribers_receive_events_sent_after_subscribing() {
+        let log = log(10);
+        let (_, mut rx) = log.subscribe(Replay::None);
+        let sent = event(PacketStep::TrustPing, "c0", "2026-01-01T10:00:00Z");
+        let id = sent.id.clone();
+        assert_eq!(log.send(sent), 1);
+        assert_eq!(rx.try_recv().unwrap().event.id, id);
+    }
+
+    #[test]
+    fn import_skips_retained_ids_and_broadcasts_the_rest() {
+        let log = log(10);
+        let existing = event(PacketStep::TrustPing, "c0", "2026-01-01T10:00:00Z");
+        log.send(existing.clone());
+        let (_, mut rx) = log.subscribe(Replay::None);
+
+        let new = event(PacketStep::TrustPong, "c0", "2026-01-01T10:00:01Z");
+        let added = log.import(vec![existing, new.clone(), new.clone()]);
+        assert_eq!(added, 1);
+        assert_eq!(log.after(None).len(), 2);
+        assert_eq!(rx.try_recv().unwrap().event.id, new.id);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn notify_broadcasts_without_retaining() {
+        let log = log(10);
+        log.send(event(PacketStep::TrustPing, "c0", "2026-01-01T10:00:00Z"));
+        let (_, mut rx) = log.subscribe(Replay::None);
+
+        assert_eq!(log.notify(PacketEvent::reset()), 1);
+        assert_eq!(rx.try_recv().unwrap().event.raw_json["action"], "reset");
+        assert_eq!(log.after(None).len(), 1);
+    }
 }
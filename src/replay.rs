@@ -0,0 +1,150 @@
+/// Offline replay — serves a recorded session archive without a mediator.
+///
+/// When `REPLAY_ARCHIVE` names a JSONL archive (see `archive.rs`), the server
+/// skips TDK/ATM initialisation and plays the archive's packet events back
+/// through a packet log, sleeping between events for the gap between their
+/// recorded timestamps divided by `REPLAY_SPEED` (default 1, 0 = no delay).
+/// Idle stretches longer than `MAX_GAP` are shortened to it.
+/// Only the read-only endpoints are served; everything else answers 503.
+use std::collections::BTreeMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::DateTime;
+use tokio::task::JoinHandle;
+use tracing::info;
+
+use crate::archive::SessionArchive;
+use crate::identity::IdentityInfo;
+use crate::packet_logger::{self, PacketEvent, PacketLog};
+
+/// Playback speed used when `REPLAY_SPEED` is unset.
+const DEFAULT_SPEED: f64 = 1.0;
+
+/// Longest pause between two events at 1x; longer recorded gaps are capped.
+const MAX_GAP: Duration = Duration::from_secs(3);
+
+pub struct ReplayState {
+    /// Identities recorded in the archive, keyed by lower-cased alias.
+    pub identities: BTreeMap<String, IdentityInfo>,
+    /// Recorded events, oldest first.
+    pub packets: Vec<PacketEvent>,
+    /// Multiplier applied to the recorded timing (0 plays without delay).
+    pub speed: f64,
+    /// Events played so far (no persistence in replay mode).
+    pub packet_tx: PacketLog,
+    player: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl ReplayState {
+    /// Load the archive at `path`, with the speed from `REPLAY_SPEED`.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let jsonl = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let mut archive = SessionArchive::parse(&jsonl).map_err(|e| format!("{path}: {e}"))?;
+        // Older archives may contain reset markers, which would clear the
+        // inspector partway through playback
+        archive.packets.retain(|packet| !packet.is_reset());
+        let speed = env::var("REPLAY_SPEED")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|s| s.is_finite() && *s >= 0.0)
+            .unwrap_or(DEFAULT_SPEED);
+        info!(
+            "Replaying {path}: {} identities, {} packet events at {speed}x",
+            archive.identities.len(),
+            archive.packets.len()
+        );
+        Ok(Self {
+            identities: archive
+                .identities
+                .into_iter()
+                .map(|i| (i.alias.to_lowercase(), i))
+                .collect(),
+            // Keep the whole archive so late subscribers see every event
+            packet_tx: packet_logger::create_packet_log_with_capacity(None, archive.packets.len()),
+            packets: archive.packets,
+            speed,
+            player: Mutex::new(None),
+        })
+    }
+
+    /// Clear the played events and play the archive from the start, stopping
+    /// any playback already running.
+    pub fn restart(self: &Arc<Self>) {
+        let mut player = self.player.lock().unwrap();
+        if let Some(task) = player.take() {
+            task.abort();
+        }
+        self.packet_tx.clear();
+        self.packet_tx.notify(PacketEvent::reset());
+
+        let state = Arc::clone(self);
+        *player = Some(tokio::spawn(async move {
+            let mut previous: Option<&PacketEvent> = None;
+            for event in &state.packets {
+                if let Some(previous) = previous {
+                    let delay = gap(previous, event, state.speed);
+                    if !delay.is_zero() {
+                        tokio::time::sleep(delay).await;
+                    }
+                }
+                let _ = state.packet_tx.send(event.clone());
+                previous = Some(event);
+            }
+            info!("Replay finished ({} events)", state.packets.len());
+        }));
+    }
+}
+
+/// Time to wait between two recorded events at `speed`, at most `MAX_GAP`
+/// scaled by `speed`. Unparseable or out-of-order timestamps give no delay.
+fn gap(previous: &PacketEvent, next: &PacketEvent, speed: f64) -> Duration {
+    if speed == 0.0 {
+        return Duration::ZERO;
+    }
+    let (Ok(from), Ok(to)) = (
+        DateTime::parse_from_rfc3339(&previous.timestamp),
+        DateTime::parse_from_rfc3339(&next.timestamp),
+    ) else {
+        return Duration::ZERO;
+    };
+    (to - from)
+        .to_std()
+        .map_or(Duration::ZERO, |d| d.min(MAX_GAP).div_f64(speed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_logger::{PacketDirection, PacketStep};
+
+    fn at(timestamp: &str) -> PacketEvent {
+        let mut event = PacketEvent::new(
+            PacketDirection::Outbound,
+            "did:peer:2.alice",
+            "did:peer:2.bob",
+            PacketStep::TrustPing,
+            serde_json::json!({}),
+            None,
+        );
+        event.timestamp = timestamp.to_string();
+        event
+    }
+
+    #[test]
+    fn gap_scales_with_speed() {
+        let (a, b) = (at("2026-01-01T10:00:00Z"), at("2026-01-01T10:00:02Z"));
+        assert_eq!(gap(&a, &b, 1.0), Duration::from_secs(2));
+        assert_eq!(gap(&a, &b, 2.0), Duration::from_secs(1));
+        assert_eq!(gap(&a, &b, 0.0), Duration::ZERO);
+        assert_eq!(gap(&b, &a, 1.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn gap_is_capped() {
+        let (a, b) = (at("2026-01-01T10:00:00Z"), at("2026-01-01T12:00:00Z"));
+        assert_eq!(gap(&a, &b, 1.0), MAX_GAP);
+        assert_eq!(gap(&a, &b, 3.0), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,592 @@
+/// SQLite persistence for a demo session — packet events, every plaintext
+/// message sent or received by our identities, and thread metadata.
+///
+/// The database (`DATABASE_PATH`, default `didcomm-demo.db`) is reloaded on
+/// startup so the packet history and threads survive a restart. Writes are
+/// queued to a dedicated writer thread so flows and handlers never wait on disk
+/// I/O. They are best-effort: a failure is logged and never fails the flow that
+/// caused it.
+use std::env;
+use std::sync::{Mutex, mpsc};
+use std::thread;
+
+use chrono::Utc;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use affinidi_messaging_didcomm::Message;
+
+use crate::packet_logger::PacketEvent;
+
+/// Database file used when `DATABASE_PATH` is unset.
+const DEFAULT_PATH: &str = "didcomm-demo.db";
+
+const PRAGMAS: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA synchronous = NORMAL;
+";
+
+/// Schema migrations: entry `n` upgrades a database at `user_version` `n` to
+/// `n + 1`. Append new steps, never edit released ones. The first step uses
+/// `IF NOT EXISTS` so databases created before versioning are adopted as-is.
+const MIGRATIONS: &[&str] = &["
+    CREATE TABLE IF NOT EXISTS packets (
+        seq            INTEGER PRIMARY KEY AUTOINCREMENT,
+        id             TEXT NOT NULL UNIQUE,
+        timestamp      TEXT NOT NULL,
+        step           TEXT NOT NULL,
+        correlation_id TEXT,
+        from_alias     TEXT,
+        to_alias       TEXT,
+        event          TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS packets_correlation ON packets (correlation_id);
+
+    CREATE TABLE IF NOT EXISTS messages (
+        id           TEXT NOT NULL,
+        direction    TEXT NOT NULL,
+        type         TEXT NOT NULL,
+        thid         TEXT NOT NULL,
+        pthid        TEXT,
+        from_did     TEXT,
+        from_alias   TEXT,
+        to_did       TEXT,
+        to_alias     TEXT,
+        created_time INTEGER,
+        recorded_at  TEXT NOT NULL,
+        message      TEXT NOT NULL,
+        PRIMARY KEY (id, direction)
+    );
+    CREATE INDEX IF NOT EXISTS messages_thid ON messages (thid);
+
+    CREATE TABLE IF NOT EXISTS threads (
+        thid          TEXT PRIMARY KEY,
+        pthid         TEXT,
+        started_at    TEXT NOT NULL,
+        updated_at    TEXT NOT NULL,
+        message_count INTEGER NOT NULL
+    );
+"];
+
+/// Whether a stored message was sent or received by one of our identities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageDirection {
+    Sent,
+    Received,
+}
+
+impl MessageDirection {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Sent => "sent",
+            Self::Received => "received",
+        }
+    }
+}
+
+/// A plaintext message as stored, with the aliases it was recorded under.
+#[derive(Debug)]
+pub struct StoredMessage {
+    pub direction: MessageDirection,
+    pub message: Message,
+    pub from_alias: Option<String>,
+    pub to_alias: Option<String>,
+}
+
+/// Persisted metadata of a thread.
+#[derive(Debug, Clone, Serialize)]
+pub struct ThreadMeta {
+    pub thid: String,
+    pub pthid: Option<String>,
+    pub started_at: String,
+    pub updated_at: String,
+    pub message_count: u64,
+}
+
+/// A write waiting for the writer thread.
+enum Write {
+    Packet {
+        id: String,
+        timestamp: String,
+        step: String,
+        correlation_id: Option<String>,
+        from_alias: Option<String>,
+        to_alias: Option<String>,
+        event: String,
+    },
+    Message {
+        id: String,
+        direction: MessageDirection,
+        type_: String,
+        thid: String,
+        pthid: Option<String>,
+        from_did: Option<String>,
+        from_alias: Option<String>,
+        to_did: Option<String>,
+        to_alias: Option<String>,
+        created_time: Option<i64>,
+        message: String,
+    },
+    ClearPackets,
+    Truncate,
+}
+
+pub struct Store {
+    /// Connection for reads (startup restore, thread metadata).
+    conn: Mutex<Connection>,
+    /// Queue drained by the writer thread, which owns its own connection.
+    writer: mpsc::Sender<Write>,
+}
+
+impl Store {
+    /// Open (or create) the database at `DATABASE_PATH`, bring its schema up
+    /// to date and start its writer thread.
+    pub fn open() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let path = env::var("DATABASE_PATH").unwrap_or_else(|_| DEFAULT_PATH.to_string());
+        let mut conn = Connection::open(&path)?;
+        conn.execute_batch(PRAGMAS)?;
+        migrate(&mut conn)?;
+        let write_conn = Connection::open(&path)?;
+        write_conn.execute_batch(PRAGMAS)?;
+        let (writer, queue) = mpsc::channel();
+        thread::Builder::new()
+            .name("session-store".into())
+            .spawn(move || {
+                for write in queue {
+                    apply(&write_conn, write);
+                }
+            })?;
+        info!("Session store: {path}");
+        Ok(Self {
+            conn: Mutex::new(conn),
+            writer,
+        })
+    }
+
+    fn queue(&self, write: Write) {
+        if self.writer.send(write).is_err() {
+            warn!("Session store writer has stopped; dropping write");
+        }
+    }
+
+    pub fn record_packet(&self, event: &PacketEvent) {
+        let step = serde_json::to_value(&event.step)
+            .ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_default();
+        self.queue(Write::Packet {
+            id: event.id.clone(),
+            timestamp: event.timestamp.clone(),
+            step,
+            correlation_id: event.correlation_id.clone(),
+            from_alias: event.from_alias.clone(),
+            to_alias: event.to_alias.clone(),
+            event: serde_json::to_string(event).unwrap_or_default(),
+        });
+    }
+
+    /// The newest `limit` packet events, oldest first.
+    pub fn recent_packets(&self, limit: usize) -> Vec<PacketEvent> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn
+            .prepare("SELECT event FROM packets ORDER BY seq DESC LIMIT ?1")
+            .and_then(|mut stmt| {
+                stmt.query_map([limit as i64], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>, _>>()
+            });
+        match result {
+            Ok(rows) => rows
+                .iter()
+                .rev()
+                .filter_map(|json| serde_json::from_str(json).ok())
+                .collect(),
+            Err(e) => {
+                warn!("Failed to load stored packets: {e}");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Store a plaintext message and update its thread's metadata. Recording
+    /// the same message in the same direction twice is a no-op.
+    pub fn record_message(
+        &self,
+        direction: MessageDirection,
+        msg: &Message,
+        from_alias: Option<&str>,
+        to_alias: Option<&str>,
+    ) {
+        self.queue(Write::Message {
+            id: msg.id.clone(),
+            direction,
+            type_: msg.type_.clone(),
+            thid: msg.thid.clone().unwrap_or_else(|| msg.id.clone()),
+            pthid: msg.pthid.clone(),
+            from_did: msg.from.clone(),
+            from_alias: from_alias.map(str::to_string),
+            to_did: msg.to.as_ref().and_then(|to| to.first()).cloned(),
+            to_alias: to_alias.map(str::to_string),
+            created_time: msg.created_time.map(|t| t as i64),
+            message: serde_json::to_string(msg).unwrap_or_default(),
+        });
+    }
+
+    /// Every stored message of type `type_`, in the order it was recorded.
+    pub fn messages_of_type(&self, type_: &str) -> Vec<StoredMessage> {
+        let conn = self.conn.lock().unwrap();
+        let result = conn
+            .prepare(
+                "SELECT direction, message, from_alias, to_alias FROM messages
+                 WHERE type = ?1 ORDER BY rowid",
+            )
+            .and_then(|mut stmt| {
+                stmt.query_map([type_], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()
+            });
+        match result {
+            Ok(rows) => rows
+                .into_iter()
+                .filter_map(|(direction, json, from_alias, to_alias)| {
+                    Some(StoredMessage {
+                        direction: if direction == "sent" {
+                            MessageDirection::Sent
+                        } else {
+                            MessageDirection::Received
+                        },
+                        message: serde_json::from_str(&json).ok()?,
+                        from_alias,
+                        to_alias,
+                    })
+                })
+                .collect(),
+            Err(e) => {
+                warn!("Failed to load stored messages: {e}");
+                Vec::new()
+            }
+        }
+    }
+
+    pub fn thread(&self, thid: &str) -> Option<ThreadMeta> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT thid, pthid, started_at, updated_at, message_count FROM threads
+             WHERE thid = ?1",
+            [thid],
+            |row| {
+                Ok(ThreadMeta {
+                    thid: row.get(0)?,
+                    pthid: row.get(1)?,
+                    started_at: row.get(2)?,
+                    updated_at: row.get(3)?,
+                    message_count: row.get::<_, i64>(4)? as u64,
+                })
+            },
+        )
+        .optional()
+        .unwrap_or_else(|e| {
+            warn!("Failed to load thread {thid}: {e}");
+            None
+        })
+    }
+
+    /// Delete the stored packet events (replaced by an imported session).
+    pub fn clear_packets(&self) {
+        self.queue(Write::ClearPackets);
+    }
+
+    /// Delete everything (`POST /api/reset`).
+    pub fn truncate(&self) {
+        self.queue(Write::Truncate);
+    }
+}
+
+/// Apply the migrations a database's `user_version` hasn't seen yet. A database
+/// written by a newer build is refused rather than modified.
+fn migrate(conn: &mut Connection) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(format!(
+            "database schema v{version} is newer than this build supports (v{})",
+            MIGRATIONS.len()
+        )
+        .into());
+    }
+    for (step, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", step + 1)?;
+        tx.commit()?;
+        info!("Session store migrated to schema v{}", step + 1);
+    }
+    Ok(())
+}
+
+/// Run one queued write on the writer thread's connection.
+fn apply(conn: &Connection, write: Write) {
+    match write {
+        Write::Packet {
+            id,
+            timestamp,
+            step,
+            correlation_id,
+            from_alias,
+            to_alias,
+            event,
+        } => {
+            let result = conn.execute(
+                "INSERT OR IGNORE INTO packets
+                     (id, timestamp, step, correlation_id, from_alias, to_alias, event)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    id,
+                    timestamp,
+                    step,
+                    correlation_id,
+                    from_alias,
+                    to_alias,
+                    event
+                ],
+            );
+            if let Err(e) = result {
+                warn!("Failed to store packet {id}: {e}");
+            }
+        }
+        Write::Message {
+            id,
+            direction,
+            type_,
+            thid,
+            pthid,
+            from_did,
+            from_alias,
+            to_did,
+            to_alias,
+            created_time,
+            message,
+        } => {
+            let now = Utc::now().to_rfc3339();
+            let result = conn
+                .execute(
+                    "INSERT OR IGNORE INTO messages
+                         (id, direction, type, thid, pthid, from_did, from_alias, to_did, to_alias,
+                          created_time, recorded_at, message)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                    params![
+                        id,
+                        direction.as_str(),
+                        type_,
+                        thid,
+                        pthid,
+                        from_did,
+                        from_alias,
+                        to_did,
+                        to_alias,
+                        created_time,
+                        now,
+                        message
+                    ],
+                )
+                .and_then(|inserted| {
+                    if inserted == 0 {
+                        return Ok(0);
+                    }
+                    conn.execute(
+                        "INSERT INTO threads (thid, pthid, started_at, updated_at, message_count)
+                         VALUES (?1, ?2, ?3, ?3, 1)
+                         ON CONFLICT (thid) DO UPDATE SET
+                             pthid = COALESCE(threads.pthid, excluded.pthid),
+                             updated_at = excluded.updated_at,
+                             message_count =
+                                 (SELECT COUNT(DISTINCT id) FROM messages WHERE thid = excluded.thid)",
+                        params![thid, pthid, now],
+                    )
+                });
+            if let Err(e) = result {
+                warn!("Failed to store message {id}: {e}");
+            }
+        }
+        Write::ClearPackets => {
+            if let Err(e) = conn.execute("DELETE FROM packets", []) {
+                warn!("Failed to clear stored packets: {e}");
+            }
+        }
+        Write::Truncate => {
+            let result = conn
+                .execute_batch("DELETE FROM packets; DELETE FROM messages; DELETE FROM threads;");
+            if let Err(e) = result {
+                warn!("Failed to truncate the session store: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// A store on an in-memory database. Queued writes stay in the returned
+    /// receiver until `flush` applies them.
+    fn store() -> (Store, mpsc::Receiver<Write>) {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        let (writer, queue) = mpsc::channel();
+        let store = Store {
+            conn: Mutex::new(conn),
+            writer,
+        };
+        (store, queue)
+    }
+
+    fn flush(store: &Store, queue: &mpsc::Receiver<Write>) {
+        let conn = store.conn.lock().unwrap();
+        for write in queue.try_iter() {
+            apply(&conn, write);
+        }
+    }
+
+    fn message(id: &str, thid: &str, pthid: Option<&str>) -> Message {
+        let mut builder = Message::build(
+            id.into(),
+            "https://didcomm.org/basicmessage/2.0/message".into(),
+            json!({"content": id}),
+        )
+        .thid(thid.into())
+        .from("did:peer:2.alice".into())
+        .to("did:peer:2.bob".into());
+        if let Some(pthid) = pthid {
+            builder = builder.pthid(pthid.into());
+        }
+        builder.finalize()
+    }
+
+    fn user_version(conn: &Connection) -> usize {
+        conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn migrate_brings_a_fresh_database_to_the_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(user_version(&conn), 0);
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+        // Running again is a no-op
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn migrate_adopts_an_unversioned_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE threads (
+                 thid TEXT PRIMARY KEY, pthid TEXT, started_at TEXT NOT NULL,
+                 updated_at TEXT NOT NULL, message_count INTEGER NOT NULL
+             );
+             INSERT INTO threads VALUES ('t1', NULL, 'a', 'a', 1);",
+        )
+        .unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM threads", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn migrate_refuses_a_newer_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        let err = migrate(&mut conn).unwrap_err();
+        assert!(err.to_string().contains("newer than this build"));
+        assert_eq!(user_version(&conn), MIGRATIONS.len() + 1);
+    }
+
+    #[test]
+    fn record_message_ignores_repeats_in_the_same_direction() {
+        let (store, queue) = store();
+        let msg = message("m1", "t1", None);
+        store.record_message(MessageDirection::Sent, &msg, Some("alice"), Some("bob"));
+        store.record_message(MessageDirection::Sent, &msg, Some("alice"), Some("bob"));
+        store.record_message(MessageDirection::Received, &msg, Some("alice"), Some("bob"));
+        flush(&store, &queue);
+
+        let stored = store.messages_of_type(&msg.type_);
+        let directions: Vec<_> = stored.iter().map(|m| m.direction).collect();
+        assert_eq!(
+            directions,
+            [MessageDirection::Sent, MessageDirection::Received]
+        );
+        // Both directions are one message in the thread
+        assert_eq!(store.thread("t1").unwrap().message_count, 1);
+    }
+
+    #[test]
+    fn thread_keeps_its_first_pthid_and_counts_messages_in_order() {
+        let (store, queue) = store();
+        for (id, pthid) in [("m1", Some("parent")), ("m2", None), ("m3", Some("other"))] {
+            store.record_message(
+                MessageDirection::Sent,
+                &message(id, "t1", pthid),
+                Some("alice"),
+                Some("bob"),
+            );
+        }
+        store.record_message(
+            MessageDirection::Sent,
+            &message("m4", "t2", None),
+            Some("alice"),
+            Some("bob"),
+        );
+        flush(&store, &queue);
+
+        let thread = store.thread("t1").unwrap();
+        assert_eq!(thread.message_count, 3);
+        assert_eq!(thread.pthid.as_deref(), Some("parent"));
+        assert!(thread.started_at <= thread.updated_at);
+        let ids: Vec<_> = store
+            .messages_of_type("https://didcomm.org/basicmessage/2.0/message")
+            .into_iter()
+            .map(|m| m.message.id)
+            .collect();
+        assert_eq!(ids, ["m1", "m2", "m3", "m4"]);
+        assert!(store.thread("missing").is_none());
+    }
+
+    #[test]
+    fn truncate_deletes_everything() {
+        let (store, queue) = store();
+        store.record_packet(&PacketEvent::reset());
+        store.record_message(
+            MessageDirection::Sent,
+            &message("m1", "t1", None),
+            Some("alice"),
+            Some("bob"),
+        );
+        flush(&store, &queue);
+        assert_eq!(store.recent_packets(10).len(), 1);
+        assert!(store.thread("t1").is_some());
+
+        store.truncate();
+        flush(&store, &queue);
+        assert!(store.recent_packets(10).is_empty());
+        assert!(store.thread("t1").is_none());
+        assert!(
+            store
+                .messages_of_type("https://didcomm.org/basicmessage/2.0/message")
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,134 @@
+/// Server-side thread tracking for `basicmessage` conversations.
+///
+/// Every message sent or received by one of our identities is recorded with
+/// its thread (`thid`, or its own ID when it starts a thread) so replies can be
+/// threaded correctly and `GET /api/threads/{id}` can return the conversation
+/// across both parties in order.
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use affinidi_messaging_didcomm::Message;
+
+/// Maximum number of messages kept (oldest evicted first).
+const MAX_MESSAGES: usize = 4096;
+
+/// A message as recorded in its thread.
+#[derive(Debug, Clone, Serialize)]
+pub struct ThreadMessage {
+    pub id: String,
+    /// Thread the message belongs to (its own ID if it started the thread).
+    pub thid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pthid: Option<String>,
+    pub from: Option<String>,
+    pub from_alias: Option<String>,
+    pub to: Option<String>,
+    pub to_alias: Option<String>,
+    pub body: Value,
+    pub created_time: Option<u64>,
+    /// True once the recipient's inbound listener picked it up.
+    pub delivered: bool,
+    #[serde(skip)]
+    seq: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    messages: HashMap<String, ThreadMessage>,
+    order: VecDeque<String>,
+    seq: u64,
+}
+
+#[derive(Default)]
+pub struct ThreadStore {
+    inner: Mutex<Inner>,
+}
+
+impl ThreadStore {
+    /// Record `msg` — sent by one of our identities (`delivered = false`) or
+    /// received by one (`delivered = true`). Recording a message that is
+    /// already known only updates its delivery state.
+    pub fn record(
+        &self,
+        msg: &Message,
+        from_alias: Option<&str>,
+        to_alias: Option<&str>,
+        delivered: bool,
+    ) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(existing) = inner.messages.get_mut(&msg.id) {
+            existing.delivered |= delivered;
+            return;
+        }
+
+        inner.seq += 1;
+        let entry = ThreadMessage {
+            id: msg.id.clone(),
+            thid: msg.thid.clone().unwrap_or_else(|| msg.id.clone()),
+            pthid: msg.pthid.clone(),
+            from: msg.from.clone(),
+            from_alias: from_alias.map(str::to_string),
+            to: msg.to.as_ref().and_then(|to| to.first().cloned()),
+            to_alias: to_alias.map(str::to_string),
+            body: msg.body.clone(),
+            created_time: msg.created_time,
+            delivered,
+            seq: inner.seq,
+        };
+        inner.order.push_back(entry.id.clone());
+        inner.messages.insert(entry.id.clone(), entry);
+        while inner.order.len() > MAX_MESSAGES {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.messages.remove(&oldest);
+            }
+        }
+    }
+
+    /// Thread ID of a recorded message.
+    pub fn thread_of(&self, msg_id: &str) -> Option<String> {
+        self.inner
+            .lock()
+            .unwrap()
+            .messages
+            .get(msg_id)
+            .map(|m| m.thid.clone())
+    }
+
+    /// Messages in thread `thid`, oldest first.
+    pub fn thread(&self, thid: &str) -> Vec<ThreadMessage> {
+        let inner = self.inner.lock().unwrap();
+        let mut messages: Vec<ThreadMessage> = inner
+            .messages
+            .values()
+            .filter(|m| m.thid == thid)
+            .cloned()
+            .collect();
+        messages.sort_by_key(|m| (m.created_time, m.seq));
+        messages
+    }
+
+    /// IDs of threads whose parent thread is `thid`.
+    pub fn children(&self, thid: &str) -> Vec<String> {
+        let inner = self.inner.lock().unwrap();
+        let mut children: Vec<&ThreadMessage> = inner
+            .messages
+            .values()
+            .filter(|m| m.pthid.as_deref() == Some(thid) && m.thid != thid)
+            .collect();
+        children.sort_by_key(|m| m.seq);
+        let mut ids: Vec<String> = Vec::new();
+        for child in children {
+            if !ids.contains(&child.thid) {
+                ids.push(child.thid.clone());
+            }
+        }
+        ids
+    }
+
+    pub fn clear(&self) {
+        *self.inner.lock().unwrap() = Inner::default();
+    }
+}
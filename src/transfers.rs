@@ -0,0 +1,520 @@
+/// File transfers sent or received by our identities.
+///
+/// The sender records the manifest it announced. The recipient buffers chunks
+/// here until every chunk named by the manifest has arrived. It then reassembles
+/// the file and checks it against the manifest's SHA-256 digest. Chunks may
+/// arrive before the manifest, up to `MAX_EARLY_CHUNKS` per transfer. Every
+/// chunk must match the manifest's chunk count and size, and the bytes held
+/// across all transfers are capped at `MAX_HELD_BYTES`.
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha256::digest;
+
+use crate::flows::file_transfer::{MAX_CHUNK_SIZE, MAX_FILE_BYTES};
+
+/// Maximum number of transfers kept (oldest evicted first).
+const MAX_TRANSFERS: usize = 64;
+
+/// Chunks buffered for a transfer whose manifest has not arrived yet.
+const MAX_EARLY_CHUNKS: usize = 16;
+
+/// Bytes held across all received transfers, buffered chunks and reassembled
+/// files alike.
+const MAX_HELD_BYTES: u64 = 128 * 1024 * 1024;
+
+/// Body of the manifest message that announces a transfer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub transfer_id: String,
+    pub filename: String,
+    pub media_type: String,
+    /// Size of the whole file in bytes.
+    pub size: u64,
+    /// Hex SHA-256 digest of the whole file.
+    pub sha256: String,
+    pub chunk_size: usize,
+    pub chunk_count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferStatus {
+    InProgress,
+    Complete,
+    Failed,
+}
+
+/// Transfer metadata as returned by `GET /api/files`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Transfer {
+    pub transfer_id: String,
+    /// Alias of our identity that sent or received the file.
+    pub owner: String,
+    /// DID of the other party.
+    pub peer: Option<String>,
+    pub direction: TransferDirection,
+    /// `None` until a recipient sees the manifest.
+    pub manifest: Option<Manifest>,
+    /// Chunks sent (sender) or received (recipient) so far.
+    pub chunks_done: u32,
+    /// File bytes carried by those chunks.
+    pub bytes_done: u64,
+    pub status: TransferStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub started_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<String>,
+}
+
+impl Transfer {
+    fn new(
+        owner: &str,
+        transfer_id: &str,
+        peer: Option<&str>,
+        direction: TransferDirection,
+    ) -> Self {
+        Self {
+            transfer_id: transfer_id.to_string(),
+            owner: owner.to_lowercase(),
+            peer: peer.map(str::to_string),
+            direction,
+            manifest: None,
+            chunks_done: 0,
+            bytes_done: 0,
+            status: TransferStatus::InProgress,
+            error: None,
+            started_at: Utc::now().to_rfc3339(),
+            completed_at: None,
+        }
+    }
+
+    fn finish(&mut self, error: Option<String>) {
+        self.status = match error {
+            Some(_) => TransferStatus::Failed,
+            None => TransferStatus::Complete,
+        };
+        self.error = error;
+        self.completed_at = Some(Utc::now().to_rfc3339());
+    }
+}
+
+struct Entry {
+    transfer: Transfer,
+    /// Received chunks by index, drained into `data` on completion.
+    chunks: BTreeMap<u32, Vec<u8>>,
+    /// The reassembled file once verified.
+    data: Option<Vec<u8>>,
+}
+
+impl Entry {
+    /// Bytes of buffered chunks and reassembled file.
+    fn held_bytes(&self) -> u64 {
+        let buffered: usize = self.chunks.values().map(Vec::len).sum();
+        (buffered + self.data.as_ref().map_or(0, Vec::len)) as u64
+    }
+
+    fn fail(&mut self, error: String) {
+        self.chunks.clear();
+        self.transfer.finish(Some(error));
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    order: VecDeque<String>,
+}
+
+impl Inner {
+    fn entry(
+        &mut self,
+        owner: &str,
+        transfer_id: &str,
+        peer: Option<&str>,
+        direction: TransferDirection,
+    ) -> &mut Entry {
+        let key = key(owner, transfer_id);
+        if !self.entries.contains_key(&key) {
+            self.order.push_back(key.clone());
+            while self.order.len() > MAX_TRANSFERS {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.entries.remove(&oldest);
+                }
+            }
+        }
+        self.entries.entry(key).or_insert_with(|| Entry {
+            transfer: Transfer::new(owner, transfer_id, peer, direction),
+            chunks: BTreeMap::new(),
+            data: None,
+        })
+    }
+
+    fn held_bytes(&self) -> u64 {
+        self.entries.values().map(Entry::held_bytes).sum()
+    }
+}
+
+fn key(owner: &str, transfer_id: &str) -> String {
+    format!("{}/{transfer_id}", owner.to_lowercase())
+}
+
+#[derive(Default)]
+pub struct TransferStore {
+    inner: Mutex<Inner>,
+}
+
+impl TransferStore {
+    /// Record a transfer `owner` is about to send to `peer`.
+    pub fn start_sending(&self, owner: &str, peer: &str, manifest: &Manifest) {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = inner.entry(
+            owner,
+            &manifest.transfer_id,
+            Some(peer),
+            TransferDirection::Sent,
+        );
+        entry.transfer.manifest = Some(manifest.clone());
+    }
+
+    /// Count one more chunk of `bytes` as sent.
+    pub fn chunk_sent(&self, owner: &str, transfer_id: &str, bytes: usize) {
+        if let Some(entry) = self
+            .inner
+            .lock()
+            .unwrap()
+            .entries
+            .get_mut(&key(owner, transfer_id))
+        {
+            entry.transfer.chunks_done += 1;
+            entry.transfer.bytes_done += bytes as u64;
+        }
+    }
+
+    /// Mark a transfer complete (`error` is `None`) or failed.
+    pub fn finish(
+        &self,
+        owner: &str,
+        transfer_id: &str,
+        error: Option<String>,
+    ) -> Option<Transfer> {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = inner.entries.get_mut(&key(owner, transfer_id))?;
+        entry.transfer.finish(error);
+        Some(entry.transfer.clone())
+    }
+
+    /// `owner` received the manifest of a transfer from `peer`.
+    pub fn manifest_received(
+        &self,
+        owner: &str,
+        peer: Option<&str>,
+        manifest: Manifest,
+    ) -> Transfer {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = inner.entry(
+            owner,
+            &manifest.transfer_id,
+            peer,
+            TransferDirection::Received,
+        );
+        if entry.transfer.manifest.is_none() {
+            let valid = check_manifest(&manifest);
+            entry.transfer.manifest = Some(manifest);
+            if let Err(error) = valid {
+                entry.fail(error);
+            }
+        }
+        try_complete(entry);
+        entry.transfer.clone()
+    }
+
+    /// `owner` received chunk `index` of a transfer from `peer`. Duplicates are
+    /// ignored; chunks that do not match the manifest or exceed the buffer
+    /// limits fail the transfer.
+    pub fn chunk_received(
+        &self,
+        owner: &str,
+        peer: Option<&str>,
+        transfer_id: &str,
+        index: u32,
+        data: Vec<u8>,
+    ) -> Transfer {
+        let mut inner = self.inner.lock().unwrap();
+        let held = inner.held_bytes();
+        let entry = inner.entry(owner, transfer_id, peer, TransferDirection::Received);
+        if entry.transfer.status == TransferStatus::InProgress && !entry.chunks.contains_key(&index)
+        {
+            match check_chunk(entry, index, data.len(), held) {
+                Ok(()) => {
+                    entry.transfer.bytes_done += data.len() as u64;
+                    entry.chunks.insert(index, data);
+                    entry.transfer.chunks_done = entry.chunks.len() as u32;
+                    try_complete(entry);
+                }
+                Err(error) => entry.fail(error),
+            }
+        }
+        entry.transfer.clone()
+    }
+
+    /// Every transfer, oldest first.
+    pub fn list(&self) -> Vec<Transfer> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .order
+            .iter()
+            .filter_map(|k| inner.entries.get(k))
+            .map(|e| e.transfer.clone())
+            .collect()
+    }
+
+    /// A transfer and, once reassembled and verified, the file's bytes.
+    pub fn get(&self, owner: &str, transfer_id: &str) -> Option<(Transfer, Option<Vec<u8>>)> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .entries
+            .get(&key(owner, transfer_id))
+            .map(|e| (e.transfer.clone(), e.data.clone()))
+    }
+
+    pub fn clear(&self) {
+        *self.inner.lock().unwrap() = Inner::default();
+    }
+}
+
+/// Check that a received manifest describes a file we are willing to hold and
+/// that its chunk count matches its size.
+fn check_manifest(manifest: &Manifest) -> Result<(), String> {
+    if manifest.chunk_size == 0 || manifest.chunk_size > MAX_CHUNK_SIZE {
+        return Err(format!(
+            "manifest chunk_size must be between 1 and {MAX_CHUNK_SIZE} bytes"
+        ));
+    }
+    if manifest.size > MAX_FILE_BYTES as u64 {
+        return Err(format!("manifest size exceeds {MAX_FILE_BYTES} bytes"));
+    }
+    let expected = manifest.size.div_ceil(manifest.chunk_size as u64);
+    if u64::from(manifest.chunk_count) != expected {
+        return Err(format!(
+            "manifest says {} chunks, its size and chunk_size give {expected}",
+            manifest.chunk_count
+        ));
+    }
+    Ok(())
+}
+
+/// Check a chunk of `len` bytes against the manifest's chunk count and size.
+fn check_chunk_size(manifest: &Manifest, index: u32, len: usize) -> Result<(), String> {
+    if index >= manifest.chunk_count {
+        return Err(format!(
+            "chunk {index} is outside the manifest's {} chunks",
+            manifest.chunk_count
+        ));
+    }
+    let offset = u64::from(index) * manifest.chunk_size as u64;
+    let expected = manifest
+        .size
+        .saturating_sub(offset)
+        .min(manifest.chunk_size as u64);
+    if len as u64 != expected {
+        return Err(format!(
+            "chunk {index} has {len} bytes, the manifest expects {expected}"
+        ));
+    }
+    Ok(())
+}
+
+/// Check whether a new chunk may be buffered for `entry`, given the bytes
+/// already `held` across all transfers.
+fn check_chunk(entry: &Entry, index: u32, len: usize, held: u64) -> Result<(), String> {
+    match &entry.transfer.manifest {
+        Some(manifest) => check_chunk_size(manifest, index, len)?,
+        None => {
+            if entry.chunks.len() >= MAX_EARLY_CHUNKS {
+                return Err(format!(
+                    "more than {MAX_EARLY_CHUNKS} chunks arrived before the manifest"
+                ));
+            }
+            if entry.transfer.bytes_done + len as u64 > MAX_FILE_BYTES as u64 {
+                return Err(format!("transfer exceeds {MAX_FILE_BYTES} bytes"));
+            }
+        }
+    }
+    if held + len as u64 > MAX_HELD_BYTES {
+        return Err(format!(
+            "received transfers already hold {held} bytes (limit {MAX_HELD_BYTES})"
+        ));
+    }
+    Ok(())
+}
+
+/// Reassemble and verify the file once the manifest and every chunk are in.
+fn try_complete(entry: &mut Entry) {
+    let Some(manifest) = &entry.transfer.manifest else {
+        return;
+    };
+    if entry.transfer.status != TransferStatus::InProgress {
+        return;
+    }
+    // Chunks buffered before the manifest are checked against it now
+    if let Some(error) = entry
+        .chunks
+        .iter()
+        .find_map(|(index, data)| check_chunk_size(manifest, *index, data.len()).err())
+    {
+        entry.fail(error);
+        return;
+    }
+    if entry.chunks.len() < manifest.chunk_count as usize {
+        return;
+    }
+
+    let data: Vec<u8> = std::mem::take(&mut entry.chunks)
+        .into_values()
+        .flatten()
+        .collect();
+    let error = if data.len() as u64 != manifest.size {
+        Some(format!(
+            "reassembled {} bytes, manifest says {}",
+            data.len(),
+            manifest.size
+        ))
+    } else if digest(data.as_slice()) != manifest.sha256 {
+        Some("SHA-256 digest does not match the manifest".to_string())
+    } else {
+        None
+    };
+    if error.is_none() {
+        entry.data = Some(data);
+    }
+    entry.transfer.finish(error);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(data: &[u8], chunk_size: usize) -> Manifest {
+        Manifest {
+            transfer_id: "t1".into(),
+            filename: "file.bin".into(),
+            media_type: "application/octet-stream".into(),
+            size: data.len() as u64,
+            sha256: digest(data),
+            chunk_size,
+            chunk_count: data.chunks(chunk_size).count() as u32,
+        }
+    }
+
+    #[test]
+    fn reassembles_chunks_received_out_of_order_before_the_manifest() {
+        let data = b"hello, chunked world";
+        let store = TransferStore::default();
+        let chunks: Vec<&[u8]> = data.chunks(8).collect();
+        for index in [2, 0, 1] {
+            let transfer =
+                store.chunk_received("bob", None, "t1", index, chunks[index as usize].to_vec());
+            assert_eq!(transfer.status, TransferStatus::InProgress);
+        }
+
+        let transfer = store.manifest_received("bob", None, manifest(data, 8));
+        assert_eq!(transfer.status, TransferStatus::Complete);
+        assert_eq!(transfer.chunks_done, 3);
+        assert_eq!(transfer.bytes_done, data.len() as u64);
+        let (_, file) = store.get("Bob", "t1").unwrap();
+        assert_eq!(file.as_deref(), Some(&data[..]));
+    }
+
+    #[test]
+    fn duplicate_chunks_are_counted_once() {
+        let data = b"0123456789";
+        let store = TransferStore::default();
+        store.manifest_received("bob", None, manifest(data, 4));
+        store.chunk_received("bob", None, "t1", 0, data[..4].to_vec());
+        let transfer = store.chunk_received("bob", None, "t1", 0, data[..4].to_vec());
+        assert_eq!(transfer.chunks_done, 1);
+        assert_eq!(transfer.bytes_done, 4);
+    }
+
+    #[test]
+    fn digest_mismatch_fails_the_transfer() {
+        let store = TransferStore::default();
+        store.manifest_received("bob", None, manifest(b"expected", 8));
+        let transfer = store.chunk_received("bob", None, "t1", 0, b"tampered".to_vec());
+        assert_eq!(transfer.status, TransferStatus::Failed);
+        assert!(transfer.error.unwrap().contains("SHA-256"));
+        assert!(store.get("bob", "t1").unwrap().1.is_none());
+    }
+
+    #[test]
+    fn chunk_outside_the_manifest_fails_the_transfer() {
+        let store = TransferStore::default();
+        store.chunk_received("bob", None, "t1", 5, b"stray".to_vec());
+        let transfer = store.manifest_received("bob", None, manifest(b"abcd", 4));
+        assert_eq!(transfer.status, TransferStatus::Failed);
+        assert!(transfer.error.unwrap().contains("chunk 5"));
+    }
+
+    #[test]
+    fn chunk_of_the_wrong_size_fails_the_transfer() {
+        let data = b"0123456789";
+        let store = TransferStore::default();
+        store.manifest_received("bob", None, manifest(data, 4));
+        let transfer = store.chunk_received("bob", None, "t1", 2, data[..4].to_vec());
+        assert_eq!(transfer.status, TransferStatus::Failed);
+        assert!(transfer.error.unwrap().contains("expects 2"));
+    }
+
+    #[test]
+    fn early_chunks_are_bounded() {
+        let store = TransferStore::default();
+        for index in 0..MAX_EARLY_CHUNKS as u32 {
+            let transfer = store.chunk_received("bob", None, "t1", index, vec![0; 4]);
+            assert_eq!(transfer.status, TransferStatus::InProgress);
+        }
+        let transfer = store.chunk_received("bob", None, "t1", MAX_EARLY_CHUNKS as u32, vec![0; 4]);
+        assert_eq!(transfer.status, TransferStatus::Failed);
+        assert_eq!(store.inner.lock().unwrap().held_bytes(), 0);
+    }
+
+    #[test]
+    fn manifest_with_inconsistent_chunk_count_fails_the_transfer() {
+        let mut manifest = manifest(b"0123456789", 4);
+        manifest.chunk_count = 100;
+        let store = TransferStore::default();
+        let transfer = store.manifest_received("bob", None, manifest);
+        assert_eq!(transfer.status, TransferStatus::Failed);
+        assert!(transfer.error.unwrap().contains("100 chunks"));
+    }
+
+    #[test]
+    fn held_bytes_are_capped_across_transfers() {
+        let chunk = vec![0; MAX_CHUNK_SIZE];
+        let store = TransferStore::default();
+        let per_transfer = MAX_EARLY_CHUNKS.min(MAX_FILE_BYTES / MAX_CHUNK_SIZE);
+        let mut failed = None;
+        'fill: for t in 0..MAX_TRANSFERS {
+            for index in 0..per_transfer as u32 {
+                let transfer =
+                    store.chunk_received("bob", None, &format!("t{t}"), index, chunk.clone());
+                if transfer.status == TransferStatus::Failed {
+                    failed = transfer.error;
+                    break 'fill;
+                }
+            }
+        }
+        assert!(failed.unwrap().contains("limit"));
+        assert!(store.inner.lock().unwrap().held_bytes() <= MAX_HELD_BYTES);
+    }
+}